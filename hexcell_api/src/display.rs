@@ -2,11 +2,36 @@ use core::ops::{Add, Sub, Div, Mul, AddAssign, SubAssign};
 
 use crate::hexcell::HexCell;
 use crate::logging::{write_log, LogLevel, log, LogMessage};
+
+mod gamma;
+mod geometry;
+mod pixel;
+mod ws2812;
+
+pub use geometry::{cosine, edge_leds, sine, LedPosition, EDGE_COUNT, LED_GEOMETRY, LED_RINGS, MAX_RADIUS, RING_COUNT};
+pub use pixel::{ChannelOrder, PixelFormat, PixelFrame, WhiteMode, MAX_PIXEL_BYTES};
+pub use ws2812::{LedTiming, SpiEncoder, BIT_TOLERANCE_NS, HIGH_TOLERANCE_NS, MAX_SYMBOL_BITS};
+
+// The board is picked with a cargo feature, the original 9 led cell when none is set
+#[cfg(all(feature = "cell-19", feature = "cell-37"))]
+compile_error!("Select at most one of the cell-19 and cell-37 features");
+
+#[cfg(not(any(feature = "cell-19", feature = "cell-37")))]
 pub const LED_COUNT: usize = 9;
+#[cfg(feature = "cell-19")]
+pub const LED_COUNT: usize = 19;
+#[cfg(all(feature = "cell-37", not(feature = "cell-19")))]
+pub const LED_COUNT: usize = 37;
+
+// One bit per led, bit n selects led n
+pub type LedMask = u64;
+pub const ALL_LEDS: LedMask = LedMask::MAX >> (LedMask::BITS as usize - LED_COUNT);
+
+const _: () = assert!(LED_COUNT <= LedMask::BITS as usize, "LedMask is too narrow for LED_COUNT");
 
 pub type LedBuffer = [Led; LED_COUNT];
 
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
 pub struct Led
 {
   pub r: u8,
@@ -32,11 +57,64 @@ impl Led
   {
     // Factor here is an 8 bit fixed point on the order of 0..1
     let factor: u8 = if elapsed < duration { ((elapsed << 8) / duration) as u8 } else { 0xFF };
+    self.blend(target, factor)
+  }
+
+  // Mixes toward target by an 8 bit fixed point factor on the order of 0..1
+  pub fn blend(&self, target: Led, factor: u8) -> Led
+  {
     let inverse_factor = (0xFF) - factor;
     let result = self.scale(inverse_factor) + target.scale(factor);
     result
   }
 
+  // As blend, but mixes in linear light so midpoints keep their brightness
+  pub fn blend_linear(&self, target: Led, factor: u8) -> Led
+  {
+    let mix = |from: u8, to: u8| -> u8 {
+      let from = srgb_to_linear(from) as u32;
+      let to = srgb_to_linear(to) as u32;
+      let mixed = ((from * (0xFF - factor as u32)) + (to * factor as u32)) / 0xFF;
+      linear_to_srgb(mixed as u16)
+    };
+    Led {
+      r: mix(self.r, target.r),
+      g: mix(self.g, target.g),
+      b: mix(self.b, target.b),
+    }
+  }
+
+  // Channel sum, clamped at full scale
+  pub fn saturating_add(self, rhs: Led) -> Led
+  {
+    Led {
+      r: self.r.saturating_add(rhs.r),
+      g: self.g.saturating_add(rhs.g),
+      b: self.b.saturating_add(rhs.b),
+    }
+  }
+
+  // Channel product normalized to full scale, so white leaves a color unchanged
+  pub fn modulate(self, rhs: Led) -> Led
+  {
+    let product = |a: u8, b: u8| -> u8 { ((a as u16 * b as u16) / 0xFF) as u8 };
+    Led {
+      r: product(self.r, rhs.r),
+      g: product(self.g, rhs.g),
+      b: product(self.b, rhs.b),
+    }
+  }
+
+  // Brightest of each channel
+  pub fn max(self, rhs: Led) -> Led
+  {
+    Led {
+      r: self.r.max(rhs.r),
+      g: self.g.max(rhs.g),
+      b: self.b.max(rhs.b),
+    }
+  }
+
   pub fn div(self, rhs: u8) -> Led
   {
     Led {
@@ -46,25 +124,23 @@ impl Led
     }
   }
 
+  // Channels multiplied by rhs, clamped at full scale
   pub fn mul(self, rhs: u8) -> Led
   {
     Led {
-      r: self.r * rhs,
-      g: self.g * rhs,
-      b: self.b * rhs,
+      r: self.r.saturating_mul(rhs),
+      g: self.g.saturating_mul(rhs),
+      b: self.b.saturating_mul(rhs),
     }
   }
 }
 
+// Operators saturate at either end of a channel rather than wrapping
 impl Add for Led
 {
   type Output = Led;
   fn add(self, rhs: Led) -> Led {
-    Led {
-      r: self.r + rhs.r,
-      g: self.g + rhs.g,
-      b: self.b + rhs.b
-    }
+    self.saturating_add(rhs)
   }
 }
 
@@ -80,9 +156,7 @@ impl Add for &Led
 impl AddAssign for Led
 {
   fn add_assign(&mut self, rhs: Self) {
-    self.r += rhs.r;
-    self.g += rhs.g;
-    self.b += rhs.b;
+    *self = self.saturating_add(rhs);
   }
 }
 
@@ -93,9 +167,9 @@ impl Mul for Led
   {
     Led 
     {
-      r: (self.r * rhs.r) as u8,
-      g: (self.g * rhs.g) as u8,
-      b: (self.b * rhs.b) as u8,
+      r: self.r.saturating_mul(rhs.r),
+      g: self.g.saturating_mul(rhs.g),
+      b: self.b.saturating_mul(rhs.b),
     }
   }
 }
@@ -119,9 +193,9 @@ impl Sub for Led
   type Output = Led;
   fn sub(self, rhs: Self) -> Self::Output {
       Led {
-        r: (self.r as i16 - rhs.r as i16) as u8,
-        g: (self.g as i16 - rhs.g as i16) as u8,
-        b: (self.b as i16 - rhs.b as i16) as u8,
+        r: self.r.saturating_sub(rhs.r),
+        g: self.g.saturating_sub(rhs.g),
+        b: self.b.saturating_sub(rhs.b),
       }
   }
 }
@@ -129,22 +203,756 @@ impl Sub for Led
 impl SubAssign for Led
 {
   fn sub_assign(&mut self, rhs: Self) {
-      self.r -= rhs.r;
-      self.g -= rhs.g;
-      self.b -= rhs.b;
+      *self = *self - rhs;
+  }
+}
+
+// Full scale of a wide channel, an 8 bit value v widens to v * 257
+pub const WIDE_MAX: u16 = 0xFFFF;
+
+// Color with 16 bits a channel, for working frames out before they are
+// quantized down to a LedBuffer. Arithmetic saturates rather than wrapping.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct WideLed
+{
+  pub r: u16,
+  pub g: u16,
+  pub b: u16
+}
+
+pub type WideBuffer = [WideLed; LED_COUNT];
+
+impl WideLed
+{
+  pub const fn widen(led: Led) -> WideLed
+  {
+    WideLed { r: led.r as u16 * 257, g: led.g as u16 * 257, b: led.b as u16 * 257 }
+  }
+
+  // Nearest 8 bit color
+  pub fn narrow(&self) -> Led
+  {
+    let round = |channel: u16| ((channel as u32 + 128) / 257) as u8;
+    Led { r: round(self.r), g: round(self.g), b: round(self.b) }
+  }
+
+  // Channels scaled by a 16 bit fixed point factor on the order of 0..1
+  pub fn scale(&self, factor: u16) -> WideLed
+  {
+    let scale = |channel: u16| ((channel as u32 * factor as u32) / WIDE_MAX as u32) as u16;
+    WideLed { r: scale(self.r), g: scale(self.g), b: scale(self.b) }
+  }
+
+  // As Led::blend_linear, with a 16 bit factor
+  pub fn blend_linear(&self, target: WideLed, factor: u16) -> WideLed
+  {
+    let mix = |from: u16, to: u16| -> u16 {
+      let from = srgb16_to_linear(from) as u64;
+      let to = srgb16_to_linear(to) as u64;
+      let mixed = ((from * (WIDE_MAX - factor) as u64) + (to * factor as u64)) / WIDE_MAX as u64;
+      linear_to_srgb16(mixed as u16)
+    };
+    WideLed {
+      r: mix(self.r, target.r),
+      g: mix(self.g, target.g),
+      b: mix(self.b, target.b),
+    }
+  }
+
+  pub fn saturating_add(self, rhs: WideLed) -> WideLed
+  {
+    WideLed {
+      r: self.r.saturating_add(rhs.r),
+      g: self.g.saturating_add(rhs.g),
+      b: self.b.saturating_add(rhs.b),
+    }
+  }
+
+  pub fn saturating_sub(self, rhs: WideLed) -> WideLed
+  {
+    WideLed {
+      r: self.r.saturating_sub(rhs.r),
+      g: self.g.saturating_sub(rhs.g),
+      b: self.b.saturating_sub(rhs.b),
+    }
+  }
+
+  // Channel product normalized to full scale, so white leaves a color unchanged
+  pub fn modulate(self, rhs: WideLed) -> WideLed
+  {
+    let product = |a: u16, b: u16| -> u16 { ((a as u32 * b as u32) / WIDE_MAX as u32) as u16 };
+    WideLed {
+      r: product(self.r, rhs.r),
+      g: product(self.g, rhs.g),
+      b: product(self.b, rhs.b),
+    }
+  }
+
+  pub fn max(self, rhs: WideLed) -> WideLed
+  {
+    WideLed {
+      r: self.r.max(rhs.r),
+      g: self.g.max(rhs.g),
+      b: self.b.max(rhs.b),
+    }
+  }
+}
+
+impl From<Led> for WideLed
+{
+  fn from(led: Led) -> WideLed
+  {
+    WideLed::widen(led)
+  }
+}
+
+impl From<WideLed> for Led
+{
+  fn from(wide: WideLed) -> Led
+  {
+    wide.narrow()
+  }
+}
+
+impl Add for WideLed
+{
+  type Output = WideLed;
+  fn add(self, rhs: WideLed) -> WideLed {
+    self.saturating_add(rhs)
+  }
+}
+
+impl AddAssign for WideLed
+{
+  fn add_assign(&mut self, rhs: Self) {
+    *self = self.saturating_add(rhs);
+  }
+}
+
+impl Sub for WideLed
+{
+  type Output = WideLed;
+  fn sub(self, rhs: WideLed) -> WideLed {
+    self.saturating_sub(rhs)
+  }
+}
+
+impl SubAssign for WideLed
+{
+  fn sub_assign(&mut self, rhs: Self) {
+    *self = self.saturating_sub(rhs);
+  }
+}
+
+// Quantizes wide frames down to 8 bits. Each led keeps the part of a level
+// that rounding dropped and carries it into the next frame, so a level
+// between two steps shows as the two alternating and slow fades stay smooth.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dither
+{
+  // Left over from the last frame, in 256ths of a step
+  error: [[u8; 3]; LED_COUNT],
+  // Rounds to the nearest step when off
+  pub enabled: bool,
+}
+
+impl Default for Dither
+{
+  fn default() -> Dither
+  {
+    Dither::new()
+  }
+}
+
+impl Dither
+{
+  pub fn new() -> Dither
+  {
+    let mut dither = Dither { error: [[0; 3]; LED_COUNT], enabled: true };
+    dither.reset();
+    dither
+  }
+
+  // Starts leds off at different points of their cycles, so neighbors
+  // holding the same level don't all step together
+  pub fn reset(&mut self)
+  {
+    for (index, error) in self.error.iter_mut().enumerate()
+    {
+      let offset = (index as u32).wrapping_mul(0x9D) as u8;
+      *error = [offset, offset.wrapping_add(0x55), offset.wrapping_add(0xAA)];
+    }
+  }
+
+  pub fn quantize(&mut self, frame: &WideBuffer) -> LedBuffer
+  {
+    let mut buffer = [Led::default(); LED_COUNT];
+    let enabled = self.enabled;
+    for ((led, wide), error) in buffer.iter_mut().zip(frame.iter()).zip(self.error.iter_mut())
+    {
+      let channel = |value: u16, error: &mut u8| -> u8 {
+        // 8.8 fixed point, full scale lands exactly on 255.0
+        let fixed = (value as u32 * 0xFF00) / WIDE_MAX as u32;
+        if !enabled
+        {
+          return ((fixed + 0x80) >> 8).min(0xFF) as u8;
+        }
+        let total = fixed + *error as u32;
+        *error = total as u8;
+        (total >> 8) as u8
+      };
+      *led = Led {
+        r: channel(wide.r, &mut error[0]),
+        g: channel(wide.g, &mut error[1]),
+        b: channel(wide.b, &mut error[2]),
+      };
+    }
+    buffer
+  }
+}
+
+// Hue, saturation, value color. Hue covers the whole wheel in 0..=255,
+// starting and ending at red
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct Hsv
+{
+  pub h: u8,
+  pub s: u8,
+  pub v: u8
+}
+
+// Hue scaled so each of the six sectors of the wheel spans 256 steps
+const HUE_SECTOR: i32 = 256;
+const HUE_WHEEL: i32 = HUE_SECTOR * 6;
+
+impl Hsv
+{
+  pub fn to_rgb(&self) -> Led
+  {
+    let v = self.v as u16;
+    let s = self.s as u16;
+    let scaled = self.h as u16 * 6;
+    let sector = scaled >> 8;
+    let rem = scaled & 0xFF;
+    // Products of two 0-255 values, divided by 255 with rounding so full
+    // scale stays full scale
+    let mul = |a: u16, b: u16| (a * b + 127) / 255;
+    let p = mul(v, 255 - s) as u8;
+    let q = mul(v, 255 - mul(s, rem)) as u8;
+    let t = mul(v, 255 - mul(s, 255 - rem)) as u8;
+    let v = self.v;
+    match sector
+    {
+      0 => Led { r: v, g: t, b: p },
+      1 => Led { r: q, g: v, b: p },
+      2 => Led { r: p, g: v, b: t },
+      3 => Led { r: p, g: q, b: v },
+      4 => Led { r: t, g: p, b: v },
+      _ => Led { r: v, g: p, b: q },
+    }
+  }
+
+  pub fn from_rgb(led: Led) -> Hsv
+  {
+    let max = led.r.max(led.g).max(led.b);
+    let min = led.r.min(led.g).min(led.b);
+    let delta = (max - min) as i32;
+    if max == 0 || delta == 0
+    {
+      // Grey, hue is meaningless
+      return Hsv { h: 0, s: 0, v: max };
+    }
+    let (r, g, b) = (led.r as i32, led.g as i32, led.b as i32);
+    let scaled = if max == led.r
+    {
+      ((g - b) * HUE_SECTOR) / delta
+    }
+    else if max == led.g
+    {
+      (2 * HUE_SECTOR) + (((b - r) * HUE_SECTOR) / delta)
+    }
+    else
+    {
+      (4 * HUE_SECTOR) + (((r - g) * HUE_SECTOR) / delta)
+    };
+    let scaled = (scaled + HUE_WHEEL) % HUE_WHEEL;
+    Hsv {
+      h: (((scaled * 256) + (HUE_WHEEL / 2)) / HUE_WHEEL) as u8,
+      s: ((delta * 255) / max as i32) as u8,
+      v: max,
+    }
+  }
+
+  // Moves hue from self toward target by an 8 bit fixed point factor, taking the
+  // short way around the wheel, or the long way (a full turn for equal hues)
+  pub fn rotate_hue(&self, target: u8, factor: u8, long: bool) -> u8
+  {
+    let short = target.wrapping_sub(self.h) as i8 as i32;
+    let distance = if !long
+    {
+      short
+    }
+    else if short > 0
+    {
+      short - 256
+    }
+    else
+    {
+      short + 256
+    };
+    (self.h as i32 + ((distance * factor as i32) / 0xFF)) as u8
+  }
+
+  // Mixes toward target, hue around the wheel and saturation/value linearly
+  pub fn blend(&self, target: Hsv, factor: u8, long: bool) -> Hsv
+  {
+    let mix = |from: u8, to: u8| -> u8 {
+      (from as i32 + (((to as i32 - from as i32) * factor as i32) / 0xFF)) as u8
+    };
+    Hsv {
+      h: self.rotate_hue(target.h, factor, long),
+      s: mix(self.s, target.s),
+      v: mix(self.v, target.v),
+    }
+  }
+}
+
+impl From<Hsv> for Led
+{
+  fn from(hsv: Hsv) -> Led
+  {
+    hsv.to_rgb()
+  }
+}
+
+impl From<Led> for Hsv
+{
+  fn from(led: Led) -> Hsv
+  {
+    Hsv::from_rgb(led)
+  }
+}
+
+// Decodes an 8 bit sRGB value to 16 bit linear light
+pub fn srgb_to_linear(value: u8) -> u16
+{
+  gamma::SRGB_TO_LINEAR[value as usize]
+}
+
+// Encodes 16 bit linear light to the nearest 8 bit sRGB value
+pub fn linear_to_srgb(value: u16) -> u8
+{
+  let low = srgb_floor(value);
+  if low < 0xFF && (gamma::SRGB_TO_LINEAR[low + 1] - value) < (value - gamma::SRGB_TO_LINEAR[low])
+  {
+    return (low + 1) as u8;
+  }
+  low as u8
+}
+
+// As srgb_to_linear for a wide channel, interpolating between table entries
+pub fn srgb16_to_linear(value: u16) -> u16
+{
+  interpolate(&gamma::SRGB_TO_LINEAR, value)
+}
+
+// Looks a wide channel up in a table indexed by 8 bit value
+fn interpolate(table: &[u16; 256], value: u16) -> u16
+{
+  let fixed = (value as u32 * 0xFF00) / WIDE_MAX as u32;
+  let (index, fraction) = ((fixed >> 8) as usize, fixed & 0xFF);
+  let from = table[index] as u32;
+  if index == 0xFF
+  {
+    return from as u16;
+  }
+  let to = table[index + 1] as u32;
+  (from + (((to - from) * fraction) >> 8)) as u16
+}
+
+// Encodes 16 bit linear light as a wide sRGB channel
+pub fn linear_to_srgb16(value: u16) -> u16
+{
+  let low = srgb_floor(value);
+  if low == 0xFF
+  {
+    return WIDE_MAX;
+  }
+  let from = gamma::SRGB_TO_LINEAR[low] as u32;
+  let to = gamma::SRGB_TO_LINEAR[low + 1] as u32;
+  let fixed = ((low as u32) << 8) + (((value as u32 - from) << 8) / (to - from));
+  ((fixed * WIDE_MAX as u32 + 0x7F80) / 0xFF00) as u16
+}
+
+// Largest 8 bit sRGB value whose linear light is not above value
+fn srgb_floor(value: u16) -> usize
+{
+  let mut low: usize = 0;
+  let mut high: usize = gamma::SRGB_TO_LINEAR.len() - 1;
+  while low < high
+  {
+    let mid = (low + high).div_ceil(2);
+    if gamma::SRGB_TO_LINEAR[mid] <= value
+    {
+      low = mid;
+    }
+    else
+    {
+      high = mid - 1;
+    }
+  }
+  low
+}
+
+// Output correction applied between the pattern buffer and the leds,
+// chosen to match the parts fitted to a board
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub enum Gamma
+{
+  #[default]
+  None,
+  Gamma22,
+  Gamma28,
+}
+
+impl Gamma
+{
+  pub fn correct(&self, value: u8) -> u8
+  {
+    match self
+    {
+      Gamma::None => value,
+      Gamma::Gamma22 => gamma::GAMMA_22[value as usize],
+      Gamma::Gamma28 => gamma::GAMMA_28[value as usize],
+    }
+  }
+
+  pub fn apply(&self, led: Led) -> Led
+  {
+    Led {
+      r: self.correct(led.r),
+      g: self.correct(led.g),
+      b: self.correct(led.b),
+    }
+  }
+
+  // As correct for a wide channel, keeping the levels between 8 bit steps
+  pub fn correct_wide(&self, value: u16) -> u16
+  {
+    match self
+    {
+      Gamma::None => value,
+      Gamma::Gamma22 => interpolate(&gamma::GAMMA_22_WIDE, value),
+      Gamma::Gamma28 => interpolate(&gamma::GAMMA_28_WIDE, value),
+    }
+  }
+
+  pub fn apply_wide(&self, led: WideLed) -> WideLed
+  {
+    WideLed {
+      r: self.correct_wide(led.r),
+      g: self.correct_wide(led.g),
+      b: self.correct_wide(led.b),
+    }
+  }
+}
+
+// Current drawn by one led, used to estimate what a frame will cost
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerModel
+{
+  // Draw of each channel driven at full scale, in mA
+  pub r_ma: u16,
+  pub g_ma: u16,
+  pub b_ma: u16,
+  // Draw of the led's driver while dark, in uA
+  pub idle_ua: u16,
+}
+
+impl Default for PowerModel
+{
+  // Typical of a 5050 WS2812B
+  fn default() -> PowerModel
+  {
+    PowerModel { r_ma: 20, g_ma: 20, b_ma: 20, idle_ua: 1000 }
+  }
+}
+
+impl PowerModel
+{
+  // Estimated draw of one led, in uA
+  pub fn led_ua(&self, led: Led) -> u32
+  {
+    let full_scale = led.r as u32 * self.r_ma as u32 + led.g as u32 * self.g_ma as u32 + led.b as u32 * self.b_ma as u32;
+    (full_scale * 1000) / 255 + self.idle_ua as u32
+  }
+
+  // Estimated draw of a whole buffer, in uA
+  pub fn buffer_ua(&self, buffer: &LedBuffer) -> u32
+  {
+    buffer.iter().map(|led| self.led_ua(*led)).sum()
+  }
+
+  // As led_ua, for a led at full precision
+  pub fn wide_led_ua(&self, led: WideLed) -> u32
+  {
+    let full_scale = led.r as u64 * self.r_ma as u64 + led.g as u64 * self.g_ma as u64 + led.b as u64 * self.b_ma as u64;
+    ((full_scale * 1000) / WIDE_MAX as u64) as u32 + self.idle_ua as u32
+  }
+
+  pub fn wide_buffer_ua(&self, frame: &WideBuffer) -> u32
+  {
+    frame.iter().map(|led| self.wide_led_ua(*led)).sum()
+  }
+}
+
+// Scale the limiter reports when a frame was left alone
+pub const FULL_POWER_SCALE: u16 = 256;
+
+// What the limiter did to the last frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerReport
+{
+  // Estimated draw of the frame as the patterns asked for it, in mA
+  pub requested_ma: u32,
+  // Estimated draw of the frame as driven, in mA
+  pub drawn_ma: u32,
+  // Brightness the frame was scaled by, out of FULL_POWER_SCALE
+  pub scale: u16,
+}
+
+impl Default for PowerReport
+{
+  fn default() -> PowerReport
+  {
+    PowerReport { requested_ma: 0, drawn_ma: 0, scale: FULL_POWER_SCALE }
+  }
+}
+
+impl PowerReport
+{
+  pub fn limited(&self) -> bool
+  {
+    self.scale < FULL_POWER_SCALE
+  }
+}
+
+// Dims a whole frame evenly whenever it would draw more than the budget.
+// Hue and the relative brightness of leds are kept, only overall level drops.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct PowerLimiter
+{
+  pub model: PowerModel,
+  // Most this cell may draw, in mA, None for no limit
+  pub cell_budget_ma: Option<u32>,
+  // This cell's share of a budget for the whole hive, in mA
+  pub hive_share_ma: Option<u32>,
+}
+
+impl PowerLimiter
+{
+  // The tighter of the two budgets, in mA
+  pub fn budget_ma(&self) -> Option<u32>
+  {
+    match (self.cell_budget_ma, self.hive_share_ma)
+    {
+      (Some(cell), Some(hive)) => Some(cell.min(hive)),
+      (cell, hive) => cell.or(hive)
+    }
+  }
+
+  // Splits a hive wide budget evenly over cells
+  pub fn set_hive_budget(&mut self, hive_budget_ma: Option<u32>, cells: usize)
+  {
+    self.hive_share_ma = hive_budget_ma.map(|budget| budget / cells.max(1) as u32);
+  }
+
+  // Scale out of FULL_POWER_SCALE that fits a frame drawing requested_ua
+  // into the budget, None when it already fits
+  fn scale_for(&self, requested_ua: u32) -> Option<u16>
+  {
+    let budget_ua = match self.budget_ma()
+    {
+      Some(budget) if (budget as u64 * 1000) < requested_ua as u64 => budget * 1000,
+      _ => return None
+    };
+    let idle_ua = self.model.idle_ua as u32 * LED_COUNT as u32;
+    let active_ua = requested_ua.saturating_sub(idle_ua).max(1);
+    // Rounds down, so the scaled frame never lands above budget
+    Some(((budget_ua.saturating_sub(idle_ua) as u64 * FULL_POWER_SCALE as u64) / active_ua as u64) as u16)
+  }
+
+  // Scales buffer in place to fit the budget. Idle draw can't be dimmed away,
+  // so a budget below it turns the leds off entirely.
+  pub fn limit(&self, buffer: &mut LedBuffer) -> PowerReport
+  {
+    let requested_ua = self.model.buffer_ua(buffer);
+    let requested_ma = requested_ua / 1000;
+    let scale = match self.scale_for(requested_ua)
+    {
+      Some(scale) => scale,
+      None => return PowerReport { requested_ma, drawn_ma: requested_ma, scale: FULL_POWER_SCALE }
+    };
+    let dim = |channel: u8| ((channel as u32 * scale as u32) >> 8) as u8;
+    for led in buffer.iter_mut()
+    {
+      *led = Led { r: dim(led.r), g: dim(led.g), b: dim(led.b) };
+    }
+    PowerReport { requested_ma, drawn_ma: self.model.buffer_ua(buffer) / 1000, scale }
+  }
+
+  // As limit, for a frame at full precision so dimming keeps the levels
+  // between 8 bit steps for the dither to show
+  pub fn limit_wide(&self, frame: &mut WideBuffer) -> PowerReport
+  {
+    let requested_ua = self.model.wide_buffer_ua(frame);
+    let requested_ma = requested_ua / 1000;
+    let scale = match self.scale_for(requested_ua)
+    {
+      Some(scale) => scale,
+      None => return PowerReport { requested_ma, drawn_ma: requested_ma, scale: FULL_POWER_SCALE }
+    };
+    let dim = |channel: u16| ((channel as u32 * scale as u32) >> 8) as u16;
+    for led in frame.iter_mut()
+    {
+      *led = WideLed { r: dim(led.r), g: dim(led.g), b: dim(led.b) };
+    }
+    PowerReport { requested_ma, drawn_ma: self.model.wide_buffer_ua(frame) / 1000, scale }
   }
 }
 
 // This may change, but should be a compile-time constant
 pub struct Display
 {
-  pub leds: LedBuffer
+  pub leds: LedBuffer,
+  pub gamma: Gamma,
+  pub power: PowerLimiter,
+  // Wire format of the parts fitted, applied last
+  pub format: PixelFormat,
+  power_report: PowerReport,
+  // Quantizes wide frames once they are corrected
+  dither: Dither,
 }
 
 impl Display {
   pub fn new() -> Display
   {
-    Display {leds: [Led::default(); LED_COUNT]}
+    Display::with_gamma(Gamma::None)
+  }
+
+  pub fn with_gamma(gamma: Gamma) -> Display
+  {
+    Display {leds: [Led::default(); LED_COUNT], gamma, power: PowerLimiter::default(), format: PixelFormat::default(), power_report: PowerReport::default(), dither: Dither::new()}
+  }
+
+  pub fn set_gamma(&mut self, gamma: Gamma)
+  {
+    self.gamma = gamma;
+  }
+
+  pub fn set_pixel_format(&mut self, format: PixelFormat)
+  {
+    self.format = format;
+  }
+
+  // Dithering shows levels between 8 bit steps by alternating between
+  // them from frame to frame, turn it off to always round to the nearest
+  pub fn set_dithering(&mut self, enabled: bool)
+  {
+    self.dither.enabled = enabled;
+  }
+
+  // Packs a finished buffer for the parts fitted
+  pub fn pack(&self, buffer: &LedBuffer) -> PixelFrame
+  {
+    self.format.pack(buffer)
+  }
+
+  pub fn set_power_model(&mut self, model: PowerModel)
+  {
+    self.power.model = model;
+  }
+
+  pub fn set_power_budget(&mut self, budget_ma: Option<u32>)
+  {
+    self.power.cell_budget_ma = budget_ma;
+  }
+
+  // Gives this cell an even share of a budget for a hive of cells
+  pub fn set_hive_power_budget(&mut self, hive_budget_ma: Option<u32>, cells: usize)
+  {
+    self.power.set_hive_budget(hive_budget_ma, cells);
+  }
+
+  // What the limiter did to the last frame driven
+  pub fn power_report(&self) -> PowerReport
+  {
+    self.power_report
+  }
+
+  // Fits buffer to the power budget, keeping the report
+  pub fn limit_power(&mut self, buffer: &mut LedBuffer) -> PowerReport
+  {
+    let report = self.power.limit(buffer);
+    self.keep_power_report(report)
+  }
+
+  // As limit_power, for a frame at full precision
+  pub fn limit_power_wide(&mut self, frame: &mut WideBuffer) -> PowerReport
+  {
+    let report = self.power.limit_wide(frame);
+    self.keep_power_report(report)
+  }
+
+  fn keep_power_report(&mut self, report: PowerReport) -> PowerReport
+  {
+    let last_scale = self.power_report.scale;
+    self.power_report = report;
+    // Only worth a line when the limiter changes its mind
+    if self.power_report.scale != last_scale
+    {
+      write_log!(LogLevel::DEBUG, "[power] {}mA requested, scaled by {}/256 to {}mA",
+        self.power_report.requested_ma, self.power_report.scale, self.power_report.drawn_ma);
+    }
+    self.power_report
+  }
+
+  // Led values as they should be driven, after output correction
+  pub fn corrected(&self) -> LedBuffer
+  {
+    let mut buffer = self.leds;
+    for led in &mut buffer
+    {
+      *led = self.gamma.apply(*led);
+    }
+    buffer
+  }
+
+  // A full precision frame as it should be driven. Correction comes first,
+  // so the dither spreads the levels the leds are actually driven at.
+  pub fn corrected_wide(&mut self, frame: &WideBuffer) -> LedBuffer
+  {
+    let corrected = self.correct_frame(frame);
+    self.dither.quantize(&corrected)
+  }
+
+  // As corrected_wide, fitted to the power budget before it is quantized
+  pub fn driven_wide(&mut self, frame: &WideBuffer) -> LedBuffer
+  {
+    // Limit after correction, current follows the drive level
+    let mut corrected = self.correct_frame(frame);
+    self.limit_power_wide(&mut corrected);
+    self.dither.quantize(&corrected)
+  }
+
+  fn correct_frame(&self, frame: &WideBuffer) -> WideBuffer
+  {
+    let mut corrected = *frame;
+    for led in &mut corrected
+    {
+      *led = self.gamma.apply_wide(*led);
+    }
+    corrected
   }
 
   pub fn clear(&mut self)
@@ -168,9 +976,25 @@ impl Display {
     }
   }
 
-  pub fn commit<T: HexCell>(&mut self, mut device: T)
+  pub fn commit<T: HexCell>(&mut self, mut device: T) -> PowerReport
   {
+    // Limit after correction, current follows the drive level
+    let mut buffer = self.corrected();
+    let report = self.limit_power(&mut buffer);
     // Call down to device or model
-    device.update_display(&self.leds);
+    device.update_display(&self.pack(&buffer));
+    report
+  }
+
+  // As commit, for a frame rendered at full precision
+  pub fn commit_wide<T: HexCell>(&mut self, frame: &WideBuffer, mut device: T) -> PowerReport
+  {
+    for (led, wide) in self.leds.iter_mut().zip(frame.iter())
+    {
+      *led = wide.narrow();
+    }
+    let buffer = self.driven_wide(frame);
+    device.update_display(&self.pack(&buffer));
+    self.power_report
   }
 }
@@ -0,0 +1,161 @@
+// Where each led sits on the cell. Patterns, port highlights and the simulator
+// all read positions from here rather than keeping their own idea of the layout.
+
+use super::{LedMask, LED_COUNT};
+
+// Leds per ring, innermost first. Leds are numbered ring by ring, each ring
+// clockwise from the top.
+#[cfg(not(any(feature = "cell-19", feature = "cell-37")))]
+pub const LED_RINGS: [usize; 2] = [3, 6];
+#[cfg(feature = "cell-19")]
+pub const LED_RINGS: [usize; 3] = [1, 6, 12];
+#[cfg(all(feature = "cell-37", not(feature = "cell-19")))]
+pub const LED_RINGS: [usize; 4] = [1, 6, 12, 18];
+pub const RING_COUNT: usize = LED_RINGS.len();
+// Hex edges, numbered clockwise from the top edge like the ports
+pub const EDGE_COUNT: u8 = 6;
+// Radius of the outer ring, positions share this scale
+pub const MAX_RADIUS: u8 = 127;
+
+const _: () = assert!(ring_total() == LED_COUNT, "LED_RINGS must account for every led");
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct LedPosition
+{
+  pub ring: u8,
+  // Place in its ring, clockwise from the top
+  pub index: u8,
+  // 256ths of a turn clockwise from the top
+  pub angle: u8,
+  // Distance from the center, 0..=MAX_RADIUS
+  pub radius: u8,
+  // x to the right, y up
+  pub x: i8,
+  pub y: i8,
+}
+
+impl LedPosition
+{
+  // The hex edge this led points towards, None for a led at the center.
+  // Worked out from the led's place in its ring rather than its rounded
+  // angle, so each edge gets an equal share of a ring. A led on a corner
+  // goes to the edge clockwise of it.
+  pub const fn facing_edge(&self) -> Option<u8>
+  {
+    if self.radius == 0
+    {
+      return None;
+    }
+    let count = LED_RINGS[self.ring as usize];
+    let sector = (self.index as usize * EDGE_COUNT as usize * 2 + count) / (count * 2);
+    Some((sector % EDGE_COUNT as usize) as u8)
+  }
+}
+
+pub const LED_GEOMETRY: [LedPosition; LED_COUNT] = layout();
+
+// Outer ring leds facing edge
+pub const fn edge_leds(edge: u8) -> LedMask
+{
+  let mut mask = 0;
+  let mut led = 0;
+  while led < LED_COUNT
+  {
+    let position = &LED_GEOMETRY[led];
+    if position.ring as usize == RING_COUNT - 1
+    {
+      if let Some(facing) = position.facing_edge()
+      {
+        if facing == edge
+        {
+          mask |= 1 << led;
+        }
+      }
+    }
+    led += 1;
+  }
+  mask
+}
+
+// First quarter of a sine wave scaled to MAX_RADIUS, 64ths of a quarter turn
+const QUARTER_SINE: [u8; 65] = [
+  0, 3, 6, 9, 12, 16, 19, 22, 25, 28, 31, 34, 37, 40, 43, 46,
+  49, 51, 54, 57, 60, 63, 65, 68, 71, 73, 76, 78, 81, 83, 85, 88,
+  90, 92, 94, 96, 98, 100, 102, 104, 106, 107, 109, 111, 112, 113, 115, 116,
+  117, 118, 120, 121, 122, 122, 123, 124, 125, 125, 126, 126, 126, 127, 127, 127,
+  127,
+];
+
+// sin of a 256ths of a turn angle, scaled to MAX_RADIUS
+pub const fn sine(angle: u8) -> i8
+{
+  let offset = (angle % 64) as usize;
+  match angle / 64
+  {
+    0 => QUARTER_SINE[offset] as i8,
+    1 => QUARTER_SINE[64 - offset] as i8,
+    2 => -(QUARTER_SINE[offset] as i8),
+    _ => -(QUARTER_SINE[64 - offset] as i8),
+  }
+}
+
+pub const fn cosine(angle: u8) -> i8
+{
+  sine(angle.wrapping_add(64))
+}
+
+const fn ring_total() -> usize
+{
+  let mut total = 0;
+  let mut ring = 0;
+  while ring < RING_COUNT
+  {
+    total += LED_RINGS[ring];
+    ring += 1;
+  }
+  total
+}
+
+// Rings are evenly spaced out to MAX_RADIUS, starting from the center when
+// the innermost ring is a single led
+const fn ring_radius(ring: usize) -> u8
+{
+  if LED_RINGS[0] == 1
+  {
+    if RING_COUNT == 1
+    {
+      return 0;
+    }
+    return ((ring * MAX_RADIUS as usize) / (RING_COUNT - 1)) as u8;
+  }
+  (((ring + 1) * MAX_RADIUS as usize) / RING_COUNT) as u8
+}
+
+const fn layout() -> [LedPosition; LED_COUNT]
+{
+  let mut leds = [LedPosition { ring: 0, index: 0, angle: 0, radius: 0, x: 0, y: 0 }; LED_COUNT];
+  let mut led = 0;
+  let mut ring = 0;
+  while ring < RING_COUNT
+  {
+    let count = LED_RINGS[ring];
+    let radius = ring_radius(ring);
+    let mut index = 0;
+    while index < count
+    {
+      let angle = ((index * 256) / count) as u8;
+      leds[led] = LedPosition {
+        ring: ring as u8,
+        index: index as u8,
+        angle,
+        radius,
+        x: ((radius as i16 * sine(angle) as i16) / MAX_RADIUS as i16) as i8,
+        y: ((radius as i16 * cosine(angle) as i16) / MAX_RADIUS as i16) as i8,
+      };
+      led += 1;
+      index += 1;
+    }
+    ring += 1;
+  }
+  leds
+}
@@ -0,0 +1,173 @@
+// Wire formats of the addressable parts a board can be fitted with. Patterns
+// only ever deal in Led, the display packs frames into the part's format on
+// the way out, so changing parts never touches pattern code.
+
+use super::{Led, LedBuffer, LED_COUNT};
+use crate::hexapi_errors::DisplayError;
+
+// Most bytes a single pixel takes on the wire
+pub const MAX_PIXEL_BYTES: usize = 4;
+
+// Order the color channels are sent in, first to last
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub enum ChannelOrder
+{
+  #[default]
+  Rgb,
+  Rbg,
+  Grb,
+  Gbr,
+  Brg,
+  Bgr,
+}
+
+impl ChannelOrder
+{
+  // Which of r, g and b goes out in each position
+  const fn positions(&self) -> [usize; 3]
+  {
+    match self
+    {
+      ChannelOrder::Rgb => [0, 1, 2],
+      ChannelOrder::Rbg => [0, 2, 1],
+      ChannelOrder::Grb => [1, 0, 2],
+      ChannelOrder::Gbr => [1, 2, 0],
+      ChannelOrder::Brg => [2, 0, 1],
+      ChannelOrder::Bgr => [2, 1, 0],
+    }
+  }
+}
+
+// How a part's white channel is driven
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub enum WhiteMode
+{
+  // RGB part, no white channel
+  #[default]
+  None,
+  // White channel fitted, but left dark
+  Off,
+  // The grey shared by all three colors is moved onto the white channel,
+  // which is whiter and draws less than mixing it
+  Extract,
+}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct PixelFormat
+{
+  pub order: ChannelOrder,
+  // White always goes out last, after the colors
+  pub white: WhiteMode,
+}
+
+impl PixelFormat
+{
+  pub const RGB: PixelFormat = PixelFormat { order: ChannelOrder::Rgb, white: WhiteMode::None };
+  pub const WS2812: PixelFormat = PixelFormat { order: ChannelOrder::Grb, white: WhiteMode::None };
+  pub const SK6812_RGBW: PixelFormat = PixelFormat { order: ChannelOrder::Grb, white: WhiteMode::Extract };
+
+  pub const fn bytes_per_pixel(&self) -> usize
+  {
+    match self.white
+    {
+      WhiteMode::None => 3,
+      _ => 4,
+    }
+  }
+
+  // Writes led in this format to the start of out, returning the bytes used
+  pub fn encode(&self, led: Led, out: &mut [u8]) -> Result<usize, DisplayError>
+  {
+    let size = self.bytes_per_pixel();
+    let out = match out.get_mut(..size)
+    {
+      Some(out) => out,
+      None => return Err(DisplayError::BufferSizeError)
+    };
+    let white = match self.white
+    {
+      WhiteMode::Extract => led.r.min(led.g).min(led.b),
+      _ => 0,
+    };
+    let channels = [led.r - white, led.g - white, led.b - white];
+    for (slot, channel) in out.iter_mut().zip(self.order.positions())
+    {
+      *slot = channels[channel];
+    }
+    if self.white != WhiteMode::None
+    {
+      out[3] = white;
+    }
+    Ok(size)
+  }
+
+  // The color the pixel at the start of bytes shows, white spread back over the colors
+  pub fn decode(&self, bytes: &[u8]) -> Result<Led, DisplayError>
+  {
+    let bytes = match bytes.get(..self.bytes_per_pixel())
+    {
+      Some(bytes) => bytes,
+      None => return Err(DisplayError::BufferSizeError)
+    };
+    let mut channels = [0u8; 3];
+    for (byte, channel) in bytes.iter().zip(self.order.positions())
+    {
+      channels[channel] = *byte;
+    }
+    let white = match self.white
+    {
+      WhiteMode::None => 0,
+      _ => bytes[3],
+    };
+    Ok(Led {
+      r: channels[0].saturating_add(white),
+      g: channels[1].saturating_add(white),
+      b: channels[2].saturating_add(white),
+    })
+  }
+
+  pub fn pack(&self, buffer: &LedBuffer) -> PixelFrame
+  {
+    let mut frame = PixelFrame { format: *self, bytes: [0; LED_COUNT * MAX_PIXEL_BYTES] };
+    let size = self.bytes_per_pixel();
+    for (led, out) in buffer.iter().zip(frame.bytes.chunks_exact_mut(size))
+    {
+      // Chunks are always a whole pixel
+      let _ = self.encode(*led, out);
+    }
+    frame
+  }
+}
+
+// A whole frame in a part's wire format, first led first
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PixelFrame
+{
+  format: PixelFormat,
+  bytes: [u8; LED_COUNT * MAX_PIXEL_BYTES],
+}
+
+impl PixelFrame
+{
+  pub fn format(&self) -> PixelFormat
+  {
+    self.format
+  }
+
+  // The bytes to send, in order
+  pub fn as_bytes(&self) -> &[u8]
+  {
+    &self.bytes[..LED_COUNT * self.format.bytes_per_pixel()]
+  }
+
+  // What led index will show, for previews and the simulator
+  pub fn led(&self, index: usize) -> Led
+  {
+    if index >= LED_COUNT
+    {
+      return Led::default();
+    }
+    let size = self.format.bytes_per_pixel();
+    self.format.decode(&self.bytes[index * size..]).unwrap_or_default()
+  }
+}
@@ -0,0 +1,179 @@
+// Drives WS2812 style one-wire leds from an SPI peripheral. Every data bit
+// becomes a short run of SPI bits that holds the line high for longer on a
+// one than on a zero, so the whole frame can be handed to DMA in one go.
+// SPI bits go out MSB first, and MOSI has to idle low between frames.
+
+use super::PixelFrame;
+use crate::hexapi_errors::DisplayError;
+
+// How far a high time may stray from the part's nominal timing
+pub const HIGH_TOLERANCE_NS: u32 = 150;
+// How far a whole bit may stray
+pub const BIT_TOLERANCE_NS: u32 = 600;
+// Longest run of SPI bits a data bit can expand to
+pub const MAX_SYMBOL_BITS: u32 = 16;
+
+const NS_PER_S: u64 = 1_000_000_000;
+const US_PER_S: u64 = 1_000_000;
+
+// One-wire timing of a part
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LedTiming
+{
+  // High time of a zero bit, in ns
+  pub t0h_ns: u32,
+  // High time of a one bit, in ns
+  pub t1h_ns: u32,
+  // Period of every bit, in ns
+  pub bit_ns: u32,
+  // Time held low to latch a frame, in us
+  pub reset_us: u32,
+}
+
+impl LedTiming
+{
+  // Newer WS2812B parts need the long reset, older ones are happy with it too
+  pub const WS2812B: LedTiming = LedTiming { t0h_ns: 400, t1h_ns: 800, bit_ns: 1250, reset_us: 280 };
+  pub const SK6812: LedTiming = LedTiming { t0h_ns: 300, t1h_ns: 600, bit_ns: 1250, reset_us: 80 };
+}
+
+impl Default for LedTiming
+{
+  fn default() -> LedTiming
+  {
+    LedTiming::WS2812B
+  }
+}
+
+// Nearest whole number of SPI bits to ns
+const fn spi_bits(ns: u32, spi_hz: u32) -> u64
+{
+  (ns as u64 * spi_hz as u64 + NS_PER_S / 2) / NS_PER_S
+}
+
+// Whether bits of SPI clock land within tolerance of ns
+const fn within(bits: u64, spi_hz: u32, ns: u32, tolerance_ns: u32) -> bool
+{
+  // Compared scaled by the clock, to stay in whole numbers
+  let actual = bits * NS_PER_S;
+  let target = ns as u64 * spi_hz as u64;
+  actual.abs_diff(target) <= tolerance_ns as u64 * spi_hz as u64
+}
+
+// A symbol_bits long run of SPI bits, high for the first high of them
+const fn symbol(high: u32, symbol_bits: u32) -> u32
+{
+  ((1 << high) - 1) << (symbol_bits - high)
+}
+
+// Turns pixel bytes into an SPI bitstream for a given clock and part
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpiEncoder
+{
+  spi_hz: u32,
+  // SPI bits per data bit
+  symbol_bits: u32,
+  // Data bits as SPI bits, in the low symbol_bits bits
+  zero: u32,
+  one: u32,
+  reset_bytes: usize,
+}
+
+impl SpiEncoder
+{
+  // Works out the bit patterns for timing at spi_hz, failing if the clock
+  // can't get close enough to the part's timing
+  pub const fn new(spi_hz: u32, timing: LedTiming) -> Result<SpiEncoder, DisplayError>
+  {
+    let symbol_bits = spi_bits(timing.bit_ns, spi_hz);
+    let zero_high = spi_bits(timing.t0h_ns, spi_hz);
+    let one_high = spi_bits(timing.t1h_ns, spi_hz);
+    // Both need some high time, and a one needs some low time to end on
+    if zero_high == 0 || one_high <= zero_high || one_high >= symbol_bits || symbol_bits > MAX_SYMBOL_BITS as u64
+    {
+      return Err(DisplayError::UnsupportedClockError);
+    }
+    if !within(zero_high, spi_hz, timing.t0h_ns, HIGH_TOLERANCE_NS)
+      || !within(one_high, spi_hz, timing.t1h_ns, HIGH_TOLERANCE_NS)
+      || !within(symbol_bits, spi_hz, timing.bit_ns, BIT_TOLERANCE_NS)
+    {
+      return Err(DisplayError::UnsupportedClockError);
+    }
+    let symbol_bits = symbol_bits as u32;
+    let reset_bits = (timing.reset_us as u64 * spi_hz as u64).div_ceil(US_PER_S);
+    Ok(SpiEncoder {
+      spi_hz,
+      symbol_bits,
+      zero: symbol(zero_high as u32, symbol_bits),
+      one: symbol(one_high as u32, symbol_bits),
+      reset_bytes: reset_bits.div_ceil(8) as usize,
+    })
+  }
+
+  pub const fn spi_hz(&self) -> u32
+  {
+    self.spi_hz
+  }
+
+  // SPI bits each data bit becomes
+  pub const fn symbol_bits(&self) -> u32
+  {
+    self.symbol_bits
+  }
+
+  // Low bytes sent after the data to latch it
+  pub const fn reset_bytes(&self) -> usize
+  {
+    self.reset_bytes
+  }
+
+  // Bytes of SPI output for data bytes of pixels, reset gap included. Const,
+  // so a DMA buffer can be sized from it.
+  pub const fn encoded_len(&self, data: usize) -> usize
+  {
+    // Eight data bits to the byte, so each byte comes out as symbol_bits bytes
+    data * self.symbol_bits as usize + self.reset_bytes
+  }
+
+  // Writes data to the start of out followed by the reset gap, returning the
+  // bytes used
+  pub fn encode_bytes(&self, data: &[u8], out: &mut [u8]) -> Result<usize, DisplayError>
+  {
+    let length = self.encoded_len(data.len());
+    let out = match out.get_mut(..length)
+    {
+      Some(out) => out,
+      None => return Err(DisplayError::BufferSizeError)
+    };
+    let mut written = 0;
+    // SPI bits waiting to be written, oldest highest
+    let mut pending: u32 = 0;
+    let mut pending_bits: u32 = 0;
+    for byte in data
+    {
+      for bit in (0..8).rev()
+      {
+        let symbol = if (byte >> bit) & 1 == 1 { self.one } else { self.zero };
+        pending = (pending << self.symbol_bits) | symbol;
+        pending_bits += self.symbol_bits;
+        while pending_bits >= 8
+        {
+          pending_bits -= 8;
+          out[written] = (pending >> pending_bits) as u8;
+          written += 1;
+        }
+        pending &= (1 << pending_bits) - 1;
+      }
+    }
+    for byte in &mut out[written..]
+    {
+      *byte = 0;
+    }
+    Ok(length)
+  }
+
+  pub fn encode(&self, frame: &PixelFrame, out: &mut [u8]) -> Result<usize, DisplayError>
+  {
+    self.encode_bytes(frame.as_bytes(), out)
+  }
+}
@@ -36,3 +36,13 @@ pub enum GenError
   #[error("{variant}, no messages in queue")]
   EmptyQueueError,
 }
+
+#[derive(Clone, Copy, ErrorCategory)]
+#[repr(u8)]
+pub enum DisplayError
+{
+  #[error("{variant}, spi clock can't meet the led timing")]
+  UnsupportedClockError,
+  #[error("{variant}, output buffer too small for the frame")]
+  BufferSizeError,
+}
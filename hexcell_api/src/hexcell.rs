@@ -1,14 +1,14 @@
 use embedded_error_chain::prelude::*;
-use embedded_time::duration::*;
 
 use crate::messaging::Message;
-use crate::display::LedBuffer;
+use crate::display::PixelFrame;
+use crate::timer::Timestamp;
 use crate::hexapi_errors::{NetworkError, PhyError};
 
 pub trait HexCell
 {
-  // Writes buffer to leds (via SPI or other interface)
-  fn update_display(&mut self, buffer: &LedBuffer);
+  // Writes a frame, already in the parts' pixel format, to leds (via SPI or other interface)
+  fn update_display(&mut self, frame: &PixelFrame);
   // Sends a signal
   fn send_signal(&mut self, id: u8, value: u8) -> Result<(), PhyError>;
   // Called when a signal is received
@@ -28,5 +28,5 @@ pub trait HexCell
   // Attempts to send a message to a specified address
   fn send_message(&mut self, msg:&Message) -> Result<(), Error<NetworkError>>;
   // Pumps main logic (scheduler while loop, etc)
-  fn update(&mut self, now: Microseconds<u32>);
+  fn update(&mut self, now: Timestamp);
 }
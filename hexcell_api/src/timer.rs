@@ -1,6 +1,127 @@
+use core::cell::Cell;
+use core::ops::Add;
 use embedded_time::duration::*;
 
+/// A free running microsecond counter, wraps about every 71 minutes
 pub trait Timer32
 {
     fn now(&self) -> Microseconds<u32>;
 }
+
+/// A microsecond counter that never wraps in practice
+pub trait Timer64
+{
+    fn now64(&self) -> Timestamp;
+}
+
+/// Microseconds since boot. 64 bits outlasts any installation (over half a
+/// million years), so timestamps can be compared and subtracted directly.
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Timestamp(u64);
+
+impl Timestamp
+{
+    pub const ZERO: Timestamp = Timestamp(0);
+
+    pub const fn from_micros(micros: u64) -> Timestamp
+    {
+        Timestamp(micros)
+    }
+
+    pub const fn micros(&self) -> u64
+    {
+        self.0
+    }
+
+    /// Low 32 bits, as a Timer32 would have read at the same moment
+    pub fn wrapped(&self) -> Microseconds<u32>
+    {
+        Microseconds(self.0 as u32)
+    }
+
+    /// Time from earlier until self, zero if earlier is actually later
+    pub fn since(&self, earlier: Timestamp) -> Microseconds<u64>
+    {
+        Microseconds(self.0.saturating_sub(earlier.0))
+    }
+
+    /// As since, clamped to the largest step a Microseconds<u32> can carry
+    pub fn delta(&self, earlier: Timestamp) -> Microseconds<u32>
+    {
+        Microseconds(u32::try_from(self.since(earlier).integer()).unwrap_or(u32::MAX))
+    }
+
+    /// Moves the timestamp by a signed number of microseconds, stopping at zero
+    pub fn offset(&self, micros: i64) -> Timestamp
+    {
+        Timestamp(self.0.saturating_add_signed(micros))
+    }
+}
+
+impl Add<Microseconds<u32>> for Timestamp
+{
+    type Output = Timestamp;
+    fn add(self, rhs: Microseconds<u32>) -> Timestamp
+    {
+        Timestamp(self.0.saturating_add(rhs.integer() as u64))
+    }
+}
+
+/// Widens readings of a wrapping 32 bit counter into Timestamps. A reading
+/// lower than the last one counts as a wrap, so it has to be fed at least
+/// once per wrap period.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct WrapCounter
+{
+    wraps: u32,
+    last: u32,
+}
+
+impl WrapCounter
+{
+    pub const fn new() -> WrapCounter
+    {
+        WrapCounter { wraps: 0, last: 0 }
+    }
+
+    pub fn extend(&mut self, now: Microseconds<u32>) -> Timestamp
+    {
+        if now.integer() < self.last
+        {
+            self.wraps += 1;
+        }
+        self.last = now.integer();
+        Timestamp(((self.wraps as u64) << 32) | self.last as u64)
+    }
+}
+
+/// Gives any Timer32 a 64 bit base
+pub struct ExtendedTimer<T: Timer32>
+{
+    timer: T,
+    counter: Cell<WrapCounter>,
+}
+
+impl<T: Timer32> ExtendedTimer<T>
+{
+    pub fn new(timer: T) -> ExtendedTimer<T>
+    {
+        ExtendedTimer { timer, counter: Cell::new(WrapCounter::new()) }
+    }
+
+    pub fn inner(&self) -> &T
+    {
+        &self.timer
+    }
+}
+
+impl<T: Timer32> Timer64 for ExtendedTimer<T>
+{
+    fn now64(&self) -> Timestamp
+    {
+        let mut counter = self.counter.get();
+        let now = counter.extend(self.timer.now());
+        self.counter.set(counter);
+        now
+    }
+}
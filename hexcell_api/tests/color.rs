@@ -0,0 +1,136 @@
+use hexcell_api::display::{linear_to_srgb, linear_to_srgb16, srgb16_to_linear, srgb_to_linear, Display, Dither, Gamma, Hsv, Led, WideBuffer, WideLed, LED_COUNT, WIDE_MAX};
+
+#[test]
+fn led_operators_saturate()
+{
+    let bright = Led { r: 250, g: 128, b: 5 };
+    let step = Led { r: 10, g: 10, b: 10 };
+    assert_eq!(bright + step, Led { r: 255, g: 138, b: 15 });
+    assert_eq!(bright - step, Led { r: 240, g: 118, b: 0 });
+    assert_eq!(bright * Led { r: 2, g: 2, b: 2 }, Led { r: 255, g: 255, b: 10 });
+    assert_eq!(bright.mul(3), Led { r: 255, g: 255, b: 15 });
+
+    let mut led = bright;
+    led += step;
+    led -= Led { r: 0, g: 200, b: 0 };
+    assert_eq!(led, Led { r: 255, g: 0, b: 15 });
+}
+
+#[test]
+fn greys_round_trip_through_hsv()
+{
+    for level in 0..=255u8
+    {
+        let grey = Led { r: level, g: level, b: level };
+        assert_eq!(Hsv::from_rgb(grey).to_rgb(), grey);
+        // Hue doesn't matter without saturation
+        assert_eq!(Hsv { h: 200, s: 0, v: level }.to_rgb(), grey);
+    }
+    assert_eq!(Hsv { h: 0, s: 255, v: 255 }.to_rgb(), Led { r: 255, g: 0, b: 0 });
+}
+
+#[test]
+fn whole_steps_pass_through_the_dither()
+{
+    let mut dither = Dither::new();
+    let color = Led { r: 37, g: 0, b: 255 };
+    for _ in 0..10
+    {
+        assert_eq!(dither.quantize(&[color.into(); LED_COUNT]), [color; LED_COUNT]);
+    }
+}
+
+#[test]
+fn dither_averages_levels_between_steps()
+{
+    // Ten and a quarter steps
+    let level = ((10 * 256 + 64) * WIDE_MAX as u32 / 0xFF00) as u16;
+    let frame: WideBuffer = [WideLed { r: level, g: 0, b: WIDE_MAX }; LED_COUNT];
+
+    let mut dither = Dither::new();
+    let mut totals = [0u32; LED_COUNT];
+    for _ in 0..256
+    {
+        for (total, led) in totals.iter_mut().zip(dither.quantize(&frame).iter())
+        {
+            assert!(led.r == 10 || led.r == 11);
+            assert_eq!((led.g, led.b), (0, 255));
+            *total += led.r as u32;
+        }
+    }
+    assert!(totals.iter().all(|total| total.abs_diff(10 * 256 + 64) <= 1), "{:?}", totals);
+
+    dither.enabled = false;
+    assert!(dither.quantize(&frame).iter().all(|led| led.r == 10));
+}
+
+#[test]
+fn srgb_round_trips_through_linear_light()
+{
+    for value in 0..=255u8
+    {
+        assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+    }
+    // Mid grey is about a fifth of the light of white
+    assert_eq!((srgb_to_linear(0), srgb_to_linear(128), srgb_to_linear(255)), (0, 14146, WIDE_MAX));
+    // Nearest step either side of the first, and at the top of the table
+    assert_eq!((linear_to_srgb(9), linear_to_srgb(11), linear_to_srgb(20)), (0, 1, 1));
+    assert_eq!((linear_to_srgb(64952), linear_to_srgb(WIDE_MAX - 1), linear_to_srgb(WIDE_MAX)), (254, 255, 255));
+    assert_eq!((linear_to_srgb16(0), linear_to_srgb16(WIDE_MAX)), (0, WIDE_MAX));
+}
+
+#[test]
+fn gamma_tables_hold_known_levels()
+{
+    for value in 0..=255u8
+    {
+        assert_eq!(Gamma::None.correct(value), value);
+    }
+    // Half drive is (128 / 255) ^ gamma of full
+    assert_eq!([Gamma::Gamma22.correct(0), Gamma::Gamma22.correct(128), Gamma::Gamma22.correct(255)], [0, 56, 255]);
+    assert_eq!([Gamma::Gamma28.correct(0), Gamma::Gamma28.correct(128), Gamma::Gamma28.correct(255)], [0, 37, 255]);
+    assert_eq!(Gamma::Gamma22.correct_wide(0), 0);
+}
+
+#[test]
+fn wide_channels_round_trip_through_linear_light()
+{
+    for value in (0..=WIDE_MAX).step_by(97).chain([WIDE_MAX])
+    {
+        let back = linear_to_srgb16(srgb16_to_linear(value));
+        // Linear light is coarser than a wide channel near black
+        let tolerance = if value < 0x0800 { 0x0100 } else { 0x10 };
+        assert!(back.abs_diff(value) <= tolerance, "{} came back as {}", value, back);
+    }
+    assert_eq!(WideLed::widen(Led { r: 1, g: 128, b: 255 }).narrow(), Led { r: 1, g: 128, b: 255 });
+}
+
+#[test]
+fn wide_gamma_matches_the_8_bit_tables()
+{
+    for gamma in [Gamma::None, Gamma::Gamma22, Gamma::Gamma28]
+    {
+        for value in 0..=255u8
+        {
+            let wide = WideLed::widen(Led { r: value, g: value, b: value });
+            assert_eq!(gamma.apply_wide(wide).narrow(), gamma.apply(Led { r: value, g: value, b: value }), "{:?} {}", gamma, value);
+        }
+        assert_eq!(gamma.correct_wide(WIDE_MAX), WIDE_MAX);
+    }
+}
+
+#[test]
+fn dim_levels_are_dithered_after_gamma()
+{
+    // Corrected to a fraction of a step, so correcting after the dither
+    // would never light it
+    let dim = Led { r: 12, g: 12, b: 12 };
+    assert_eq!(Gamma::Gamma22.apply(dim), Led::default());
+    let frame: WideBuffer = [dim.into(); LED_COUNT];
+    let mut display = Display::with_gamma(Gamma::Gamma22);
+    let total: u32 = (0..256).map(|_| display.corrected_wide(&frame)[0].r as u32).sum();
+    let expected = (Gamma::Gamma22.correct_wide(frame[0].r) as u32 * 0xFF00) / WIDE_MAX as u32;
+    assert!(total > 0 && total.abs_diff(expected) <= 1, "{} against {}", total, expected);
+    display.set_dithering(false);
+    assert_eq!(display.corrected_wide(&frame), [Led::default(); LED_COUNT]);
+}
@@ -0,0 +1,38 @@
+use hexcell_api::display::{edge_leds, LED_COUNT, LED_GEOMETRY, LED_RINGS, MAX_RADIUS, RING_COUNT};
+
+#[test]
+fn rim_leds_face_their_edges()
+{
+    let rim = LED_COUNT - LED_RINGS[RING_COUNT - 1];
+    let mut covered = 0;
+    for edge in 0..6u8
+    {
+        let mask = edge_leds(edge);
+        // An equal share of the rim each, so every port lights as many leds
+        assert_eq!(mask.count_ones() as usize, LED_RINGS[RING_COUNT - 1] / 6, "edge {}", edge);
+        for led in (0..LED_COUNT).filter(|led| mask & (1 << led) != 0)
+        {
+            assert!(led >= rim);
+            assert_eq!(LED_GEOMETRY[led].facing_edge(), Some(edge));
+        }
+        covered |= mask;
+    }
+    // Every rim led faces exactly one edge
+    assert_eq!(covered.count_ones() as usize, LED_RINGS[RING_COUNT - 1]);
+}
+
+#[test]
+fn positions_match_angles()
+{
+    let rim = LED_COUNT - LED_RINGS[RING_COUNT - 1];
+    // The rim starts at the top and runs clockwise, with y pointing up
+    assert_eq!((LED_GEOMETRY[rim].x, LED_GEOMETRY[rim].y), (0, MAX_RADIUS as i8));
+    assert!(LED_GEOMETRY[rim + 1].x > 0 && LED_GEOMETRY[rim + 1].y > 0);
+    assert!(LED_GEOMETRY[LED_COUNT - 1].x < 0 && LED_GEOMETRY[LED_COUNT - 1].y > 0);
+    for led in LED_GEOMETRY.iter()
+    {
+        let distance = ((led.x as f64).powi(2) + (led.y as f64).powi(2)).sqrt();
+        assert!((distance - led.radius as f64).abs() <= 2.0, "{:?}", led);
+        assert_eq!(led.facing_edge().is_none(), led.radius == 0);
+    }
+}
@@ -0,0 +1,67 @@
+use hexcell_api::display::{ChannelOrder, Led, LedBuffer, PixelFormat, WhiteMode, LED_COUNT};
+use hexcell_api::hexapi_errors::DisplayError;
+
+const ORANGE: Led = Led { r: 255, g: 128, b: 16 };
+
+#[test]
+fn channels_go_out_in_the_parts_order()
+{
+    let mut out = [0u8; 4];
+    assert_eq!(PixelFormat::RGB.encode(ORANGE, &mut out).unwrap(), 3);
+    assert_eq!(out[..3], [255, 128, 16]);
+    assert_eq!(PixelFormat::WS2812.encode(ORANGE, &mut out).unwrap(), 3);
+    assert_eq!(out[..3], [128, 255, 16]);
+    let bgr = PixelFormat { order: ChannelOrder::Bgr, white: WhiteMode::None };
+    bgr.encode(ORANGE, &mut out).unwrap();
+    assert_eq!(out[..3], [16, 128, 255]);
+}
+
+#[test]
+fn white_is_extracted_from_the_shared_grey()
+{
+    let mut out = [0u8; 4];
+    assert_eq!(PixelFormat::SK6812_RGBW.encode(ORANGE, &mut out).unwrap(), 4);
+    assert_eq!(out, [112, 239, 0, 16]);
+    // Shows the same color it was asked for
+    assert_eq!(PixelFormat::SK6812_RGBW.decode(&out).unwrap(), ORANGE);
+
+    let dark_white = PixelFormat { order: ChannelOrder::Rgb, white: WhiteMode::Off };
+    dark_white.encode(ORANGE, &mut out).unwrap();
+    assert_eq!(out, [255, 128, 16, 0]);
+}
+
+#[test]
+fn short_buffers_are_refused()
+{
+    // Left untouched rather than holding part of a pixel
+    let mut out = [0xAAu8; 3];
+    assert!(matches!(PixelFormat::SK6812_RGBW.encode(ORANGE, &mut out), Err(DisplayError::BufferSizeError)));
+    assert!(matches!(PixelFormat::RGB.encode(ORANGE, &mut out[..2]), Err(DisplayError::BufferSizeError)));
+    assert_eq!(out, [0xAA; 3]);
+    assert_eq!(PixelFormat::RGB.encode(ORANGE, &mut out).unwrap(), 3);
+    // Nor is a partial pixel read back
+    assert!(matches!(PixelFormat::SK6812_RGBW.decode(&out), Err(DisplayError::BufferSizeError)));
+    assert!(matches!(PixelFormat::RGB.decode(&out[..2]), Err(DisplayError::BufferSizeError)));
+    assert!(matches!(PixelFormat::RGB.decode(&[]), Err(DisplayError::BufferSizeError)));
+    assert_eq!(PixelFormat::RGB.decode(&out).unwrap(), ORANGE);
+}
+
+#[test]
+fn frames_pack_every_led()
+{
+    let mut buffer: LedBuffer = [Led::default(); LED_COUNT];
+    buffer[0] = ORANGE;
+    buffer[LED_COUNT - 1] = Led { r: 1, g: 2, b: 3 };
+    for format in [PixelFormat::RGB, PixelFormat::WS2812, PixelFormat::SK6812_RGBW]
+    {
+        let frame = format.pack(&buffer);
+        assert_eq!(frame.as_bytes().len(), LED_COUNT * format.bytes_per_pixel());
+        for (index, led) in buffer.iter().enumerate()
+        {
+            assert_eq!(frame.led(index), *led);
+        }
+    }
+    let frame = PixelFormat::WS2812.pack(&buffer);
+    assert_eq!(frame.as_bytes()[..3], [128, 255, 16]);
+    assert_eq!(frame.as_bytes()[3 * (LED_COUNT - 1)..], [2, 1, 3]);
+}
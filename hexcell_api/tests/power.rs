@@ -0,0 +1,72 @@
+use hexcell_api::display::{Display, Led, LedBuffer, PowerLimiter, PowerModel, WideBuffer, WideLed, FULL_POWER_SCALE, LED_COUNT};
+
+const WHITE: Led = Led { r: 255, g: 255, b: 255 };
+
+#[test]
+fn frames_under_budget_pass_untouched()
+{
+    let limiter = PowerLimiter { cell_budget_ma: Some(LED_COUNT as u32 * 100), ..Default::default() };
+    let mut buffer: LedBuffer = [WHITE; LED_COUNT];
+    let report = limiter.limit(&mut buffer);
+    assert_eq!(buffer, [WHITE; LED_COUNT]);
+    assert_eq!(report.scale, FULL_POWER_SCALE);
+    assert!(!report.limited());
+    assert_eq!(report.requested_ma, report.drawn_ma);
+}
+
+#[test]
+fn full_white_is_scaled_to_the_tighter_budget()
+{
+    // About a fifth of full white, whatever the board
+    let share = LED_COUNT as u32 * 11;
+    let mut limiter = PowerLimiter { model: PowerModel::default(), cell_budget_ma: Some(share * 2), ..Default::default() };
+    // Three cells sharing the hive budget, tighter than the cell budget
+    limiter.set_hive_budget(Some(share * 3), 3);
+    assert_eq!(limiter.budget_ma(), Some(share));
+
+    let mut buffer: LedBuffer = [WHITE; LED_COUNT];
+    let report = limiter.limit(&mut buffer);
+    assert!(report.limited());
+    assert_eq!(report.requested_ma, LED_COUNT as u32 * 61);
+    assert!(report.drawn_ma <= share, "drew {}mA", report.drawn_ma);
+    assert!(report.drawn_ma * 10 >= share * 9, "dimmed further than needed, {}mA", report.drawn_ma);
+    // Evenly, so the frame stays white
+    assert!(buffer.iter().all(|led| led.r == led.g && led.g == led.b && *led == buffer[0]));
+}
+
+#[test]
+fn budget_below_idle_draw_turns_leds_off()
+{
+    let limiter = PowerLimiter { cell_budget_ma: Some(1), ..Default::default() };
+    let mut buffer: LedBuffer = [WHITE; LED_COUNT];
+    let report = limiter.limit(&mut buffer);
+    assert_eq!(report.scale, 0);
+    assert_eq!(buffer, [Led::default(); LED_COUNT]);
+}
+
+#[test]
+fn wide_frames_are_limited_before_they_are_dithered()
+{
+    // Only red draws, 1A at full scale, so a dim red costs about 7.8mA
+    let model = PowerModel { r_ma: 1000, g_ma: 0, b_ma: 0, idle_ua: 0 };
+    let frame: WideBuffer = [WideLed::widen(Led { r: 2, g: 0, b: 0 }); LED_COUNT];
+    let budget = LED_COUNT as u32 * 3;
+    let limiter = PowerLimiter { model, cell_budget_ma: Some(budget), ..Default::default() };
+    let mut limited = frame;
+    let report = limiter.limit_wide(&mut limited);
+    assert!(report.limited());
+    assert!(report.drawn_ma <= budget);
+    // Dimmed to under an 8 bit step, which a narrow frame would have lost
+    assert!(limited[0].r > 0 && limited[0].r < 257, "{}", limited[0].r);
+    let mut narrow: LedBuffer = [Led { r: 2, g: 0, b: 0 }; LED_COUNT];
+    limiter.limit(&mut narrow);
+    assert_eq!(narrow[0].r, 0);
+
+    // The display keeps it, showing the dimmed level on average
+    let mut display = Display::new();
+    display.set_power_model(model);
+    display.set_power_budget(Some(budget));
+    let total: u32 = (0..256).map(|_| display.driven_wide(&frame)[0].r as u32).sum();
+    assert!(total.abs_diff(limited[0].r as u32 * 256 / 257) <= 2, "{}", total);
+    assert_eq!(display.power_report(), report);
+}
@@ -0,0 +1,68 @@
+use core::cell::Cell;
+use embedded_time::duration::*;
+use hexcell_api::timer::{ExtendedTimer, Timer32, Timer64, Timestamp, WrapCounter};
+
+// A Timer32 the test can wind forward by hand
+struct FakeTimer
+{
+    now: Cell<u32>,
+}
+
+impl FakeTimer
+{
+    fn advance(&self, micros: u32)
+    {
+        self.now.set(self.now.get().wrapping_add(micros));
+    }
+}
+
+impl Timer32 for FakeTimer
+{
+    fn now(&self) -> Microseconds<u32>
+    {
+        Microseconds(self.now.get())
+    }
+}
+
+#[test]
+fn counter_carries_across_wrap()
+{
+    let mut counter = WrapCounter::new();
+    let before = counter.extend(Microseconds(u32::MAX - 10));
+    let after = counter.extend(Microseconds(5));
+    assert_eq!(before.micros(), u32::MAX as u64 - 10);
+    assert_eq!(after.micros(), (1 << 32) + 5);
+    assert_eq!(after.since(before), Microseconds(16u64));
+    assert_eq!(after.delta(before), Microseconds(16u32));
+}
+
+#[test]
+fn extended_timer_is_monotonic_over_many_wraps()
+{
+    let timer = ExtendedTimer::new(FakeTimer { now: Cell::new(u32::MAX - 500_000) });
+    let mut last = timer.now64();
+    // Half an hour per step, a little over a day in all
+    for _ in 0..50
+    {
+        timer.inner().advance(1_800_000_000);
+        let now = timer.now64();
+        assert!(now > last);
+        assert_eq!(now.since(last), Microseconds(1_800_000_000u64));
+        last = now;
+    }
+    assert_eq!(last.micros(), u32::MAX as u64 - 500_000 + 50 * 1_800_000_000);
+    assert_eq!(last.wrapped(), timer.inner().now());
+}
+
+#[test]
+fn deltas_never_go_negative_or_wrap()
+{
+    let early = Timestamp::from_micros(1_000);
+    let late = Timestamp::from_micros(1_000 + (1 << 33));
+    assert_eq!(early.since(late), Microseconds(0u64));
+    assert_eq!(early.delta(late), Microseconds(0u32));
+    // Too long for a 32 bit step, clamped rather than wrapped
+    assert_eq!(late.delta(early), Microseconds(u32::MAX));
+    assert_eq!(early.offset(-2_000), Timestamp::ZERO);
+    assert_eq!(early + Microseconds(500u32), Timestamp::from_micros(1_500));
+}
@@ -0,0 +1,88 @@
+use hexcell_api::display::{Led, LedTiming, PixelFormat, SpiEncoder, LED_COUNT};
+use hexcell_api::hexapi_errors::DisplayError;
+
+fn encode(spi_hz: u32, timing: LedTiming, data: &[u8]) -> Vec<u8>
+{
+    let encoder = SpiEncoder::new(spi_hz, timing).expect("Clock rejected");
+    let mut out = vec![0xAA; encoder.encoded_len(data.len())];
+    let length = encoder.encode_bytes(data, &mut out).expect("Buffer rejected");
+    assert_eq!(length, out.len());
+    out
+}
+
+// Data followed by reset bytes of low
+fn golden(data: &[u8], reset: usize) -> Vec<u8>
+{
+    let mut bytes = data.to_vec();
+    bytes.resize(data.len() + reset, 0);
+    bytes
+}
+
+#[test]
+fn three_bit_symbols_at_2_4mhz()
+{
+    // 0 is 100, 1 is 110, 280us of reset is 84 bytes
+    let out = encode(2_400_000, LedTiming::WS2812B, &[0xFF, 0x00, 0x80]);
+    assert_eq!(out, golden(&[0xDB, 0x6D, 0xB6, 0x92, 0x49, 0x24, 0xD2, 0x49, 0x24], 84));
+}
+
+#[test]
+fn four_bit_symbols_at_3_2mhz()
+{
+    // 0 is 1000, 1 is 1110
+    let out = encode(3_200_000, LedTiming::WS2812B, &[0xA5]);
+    assert_eq!(out, golden(&[0xE8, 0xE8, 0x8E, 0x8E], 112));
+    // The SK6812 wants a shorter one, and a shorter reset
+    let out = encode(3_200_000, LedTiming::SK6812, &[0xA5]);
+    assert_eq!(out, golden(&[0xC8, 0xC8, 0x8C, 0x8C], 32));
+}
+
+#[test]
+fn five_bit_symbols_straddle_bytes()
+{
+    // 0 is 11000, 1 is 11100
+    let out = encode(4_000_000, LedTiming::WS2812B, &[0x80]);
+    assert_eq!(out, golden(&[0xE6, 0x31, 0x8C, 0x63, 0x18], 140));
+}
+
+#[test]
+fn byte_symbols_at_6_4mhz()
+{
+    let out = encode(6_400_000, LedTiming::WS2812B, &[0xF0]);
+    assert_eq!(out, golden(&[0xF8, 0xF8, 0xF8, 0xF8, 0xE0, 0xE0, 0xE0, 0xE0], 224));
+}
+
+#[test]
+fn frames_encode_their_wire_bytes()
+{
+    let mut buffer = [Led::default(); LED_COUNT];
+    buffer[0] = Led { r: 0x00, g: 0xFF, b: 0x80 };
+    let frame = PixelFormat::WS2812.pack(&buffer);
+    let encoder = SpiEncoder::new(2_400_000, LedTiming::WS2812B).unwrap();
+    let mut out = [0u8; 3 * 3 * LED_COUNT + 84];
+    assert_eq!(encoder.encode(&frame, &mut out).unwrap(), out.len());
+    // Green goes first on a WS2812, every other led is off
+    assert_eq!(out[..9], [0xDB, 0x6D, 0xB6, 0x92, 0x49, 0x24, 0xD2, 0x49, 0x24]);
+    assert!(out[9..9 * LED_COUNT].chunks(3).all(|zero| zero == [0x92, 0x49, 0x24]));
+    assert!(out[9 * LED_COUNT..].iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn clocks_that_miss_the_timing_are_rejected()
+{
+    // Too slow for any high time, a one held too long, and too many bits a symbol
+    for spi_hz in [1_000_000, 2_000_000, 20_000_000]
+    {
+        assert!(matches!(SpiEncoder::new(spi_hz, LedTiming::WS2812B), Err(DisplayError::UnsupportedClockError)), "{}Hz", spi_hz);
+    }
+    // A zero and a one come out the same length
+    assert!(matches!(SpiEncoder::new(2_400_000, LedTiming::SK6812), Err(DisplayError::UnsupportedClockError)));
+}
+
+#[test]
+fn short_buffers_are_rejected()
+{
+    let encoder = SpiEncoder::new(2_400_000, LedTiming::WS2812B).unwrap();
+    let mut out = [0u8; 8 + 84];
+    assert!(matches!(encoder.encode_bytes(&[0, 0, 0], &mut out), Err(DisplayError::BufferSizeError)));
+}
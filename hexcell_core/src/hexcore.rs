@@ -1,15 +1,77 @@
 
 use embedded_time::duration::*;
-use hexcell_api::display::LedBuffer;
+use embedded_error_chain::Error;
+use hexcell_api::display::{edge_leds, Led, LedBuffer, WideBuffer};
+use hexcell_api::hexapi_errors::NetworkError;
+use hexcell_api::messaging::Message;
+use hexcell_api::timer::Timestamp;
+use zerocopy::AsBytes;
 use crate::{patterns::PatternEngine, networking::{NetworkFSM, NetworkId}, scheduler::Scheduler};
+use crate::networking::{query_payload, MessageStatus, NetworkQuery};
+use crate::hexcore_errors::PatternError;
+use crate::patterns::{LedMask, Pattern, PatternBuilder, PatternElement, PatternEvent, PatternId};
+use crate::patterns::easing::Easing;
+use crate::patterns::playback::Playback;
+use crate::patterns::presets::{PresetCommand, PresetId, PresetParams, PresetRegistry, PresetScope};
+use crate::ports::{HardPort, PORT_EDGES};
 use core::cell::RefCell;
 
+// Two quick green blinks
+fn default_connect_pattern() -> Pattern
+{
+    let blink = PatternElement {
+        pattern: PatternId::Blink,
+        color: Led { r: 0, g: 255, b: 0 },
+        duration: Microseconds(250_000),
+        ..Default::default()
+    };
+    PatternBuilder::new().then(blink).then(blink).finish()
+}
+
+// Red, fading out
+fn default_disconnect_pattern() -> Pattern
+{
+    let red = Led { r: 255, g: 0, b: 0 };
+    PatternBuilder::new()
+    .then(PatternElement { pattern: PatternId::Solid, color: red, duration: Microseconds(250_000), ..Default::default() })
+    .then(PatternElement { pattern: PatternId::Fade, color: Led::default(), duration: Microseconds(500_000), ..Default::default() })
+    .finish()
+}
+
+// Told about every cursor event, e.g. to drive a controller's UI
+pub type PatternEventHandler = fn(&PatternEvent);
+
+// Leds facing the edge a port sits on
+fn port_led_mask(port: u8) -> LedMask
+{
+    match PORT_EDGES.get(port as usize)
+    {
+        Some(edge) => edge_leds(*edge),
+        None => 0
+    }
+}
+
 // Hex Cell Core logic
 pub struct HexCellCore
 {
     network: NetworkFSM,
     pattern_engine: PatternEngine,
-    last_tick: Microseconds<u32>,
+    // Network time of the last tick
+    last_tick: Timestamp,
+    // Played on the edge of a port when a neighbor is plugged in or removed
+    connect_pattern: Pattern,
+    disconnect_pattern: Pattern,
+    presets: PresetRegistry,
+    // Sequence of the last hive wide preset command applied
+    preset_sequence: Option<u8>,
+    uid: u32,
+    // Started as soon as the base layer finishes, and whether it loops
+    chained_show: Option<(Pattern, bool)>,
+    // Set when the base layer finishes with nothing chained after it
+    show_finished: bool,
+    pattern_event_handler: Option<PatternEventHandler>,
+    // How long a new show or preset takes to fade in over the old one
+    show_crossfade: Microseconds<u32>,
 }
 
 impl HexCellCore
@@ -21,26 +83,249 @@ impl HexCellCore
         HexCellCore {
             network: NetworkFSM::new(scheduler, id),
             pattern_engine: PatternEngine::new(),
-            last_tick: Microseconds(0),
+            last_tick: Timestamp::ZERO,
+            connect_pattern: default_connect_pattern(),
+            disconnect_pattern: default_disconnect_pattern(),
+            presets: PresetRegistry::new(),
+            preset_sequence: None,
+            uid: 0,
+            chained_show: None,
+            show_finished: false,
+            pattern_event_handler: None,
+            show_crossfade: Microseconds(0),
         }
     }
 
-    pub fn init(&mut self, now: Microseconds<u32>)
+    pub fn init(&mut self, now: Timestamp)
     {
         self.network.init();
-        self.last_tick = now;
+        self.last_tick = self.network.network_time(now);
     }
 
-    pub fn tick(&mut self, now: Microseconds<u32>)
+    pub fn tick(&mut self, now: Timestamp)
     {
-        let delta = now - self.last_tick;
         self.network.update();
-        self.pattern_engine.run(delta);
-        self.last_tick = now;
+        // Patterns run on network time so neighbors stay in step
+        let network_now = self.network.network_time(now);
+        // Spatial patterns follow the position assigned by the network
+        let id = self.network.id();
+        self.pattern_engine.set_position(id.x(), id.y());
+        if self.network.take_time_resynced()
+        {
+            self.pattern_engine.align(network_now);
+            self.pattern_engine.run(Microseconds(0));
+        }
+        else
+        {
+            self.pattern_engine.run(network_now.delta(self.last_tick));
+        }
+        self.last_tick = network_now;
+        self.handle_pattern_events();
+    }
+
+    // Passes cursor events on, and moves to the next show once the base layer is done
+    fn handle_pattern_events(&mut self)
+    {
+        while let Some(event) = self.pattern_engine.poll_event()
+        {
+            if let Some(handler) = self.pattern_event_handler
+            {
+                handler(&event);
+            }
+        }
+        if !self.pattern_engine.take_base_finished() || self.pattern_engine.layer_active(0)
+        {
+            return;
+        }
+        match self.chained_show.take()
+        {
+            Some((pattern, looping)) => {
+                if self.play_show(pattern, looping).is_err()
+                {
+                    self.show_finished = true;
+                }
+            },
+            None => self.show_finished = true
+        }
+    }
+
+    pub fn set_pattern_event_handler(&mut self, handler: Option<PatternEventHandler>)
+    {
+        self.pattern_event_handler = handler;
+    }
+
+    /// Replaces the base layer of every led with pattern, played once or looped
+    pub fn play_show(&mut self, pattern: Pattern, looping: bool) -> Result<(), PatternError>
+    {
+        if pattern.is_empty()
+        {
+            return Err(PatternError::InvalidPatternError);
+        }
+        self.pattern_engine.crossfade_pattern(0, pattern, self.show_crossfade, Easing::Linear)?;
+        if looping
+        {
+            self.pattern_engine.play_pattern(0, 0, false)?;
+            self.pattern_engine.align(self.last_tick);
+        }
+        else
+        {
+            self.pattern_engine.play_pattern_once(0, 0)?;
+        }
+        self.show_finished = false;
+        Ok(())
+    }
+
+    /// Fade time used whenever a show or preset replaces another, zero cuts
+    pub fn set_show_crossfade(&mut self, duration: Microseconds<u32>)
+    {
+        self.show_crossfade = duration;
+    }
+
+    /// Queues pattern to follow the current show the moment it finishes,
+    /// replacing anything already queued
+    pub fn chain_show(&mut self, pattern: Pattern, looping: bool)
+    {
+        self.chained_show = Some((pattern, looping));
+    }
+
+    /// True once after the base layer finishes with nothing chained
+    pub fn take_show_finished(&mut self) -> bool
+    {
+        core::mem::take(&mut self.show_finished)
+    }
+
+    /// Tells a controller through port that this cell's show has finished
+    pub fn show_finished_message(&mut self, port: HardPort) -> Message
+    {
+        self.network.command_message(port as u8, MessageStatus::STATUS_OK, NetworkQuery::SHOWDONE, &self.uid.to_le_bytes())
+    }
+
+    // Seeds procedural patterns from the device's unique id, see HexCell::get_uid
+    pub fn set_uid(&mut self, uid: u32)
+    {
+        self.uid = uid;
+        self.pattern_engine.set_seed(uid);
+    }
+
+    pub fn make_time_root(&mut self)
+    {
+        self.network.make_time_root();
+    }
+
+    pub fn network_time(&self, now: Timestamp) -> Timestamp
+    {
+        self.network.network_time(now)
+    }
+
+    pub fn time_sync_query(&mut self, port: HardPort, now: Timestamp) -> Message
+    {
+        self.network.time_sync_query(port, now)
+    }
+
+    pub fn handle_time_sync(&mut self, msg: &Message, now: Timestamp) -> Result<Option<Message>, Error<NetworkError>>
+    {
+        self.network.handle_time_sync(msg, now)
     }
 
     pub fn pattern_buffer(&self) -> LedBuffer
     {
         self.pattern_engine.get_output_buffer()
     }
+
+    /// The pattern buffer at full precision, to hand to Display::commit_wide
+    pub fn pattern_frame(&self) -> WideBuffer
+    {
+        self.pattern_engine.get_wide_output_buffer()
+    }
+
+    pub fn set_port_event_patterns(&mut self, connect: Pattern, disconnect: Pattern)
+    {
+        self.connect_pattern = connect;
+        self.disconnect_pattern = disconnect;
+    }
+
+    // Confirms a new link by flashing the edge it was made on
+    pub fn port_connected(&mut self, port: u8)
+    {
+        self.pattern_engine.play_override(port_led_mask(port), self.connect_pattern.clone());
+    }
+
+    pub fn port_disconnected(&mut self, port: u8)
+    {
+        self.pattern_engine.play_override(port_led_mask(port), self.disconnect_pattern.clone());
+    }
+
+    // Live control over every pattern this cell plays
+    pub fn playback_mut(&mut self) -> &mut Playback
+    {
+        self.pattern_engine.playback_mut()
+    }
+
+    pub fn presets_mut(&mut self) -> &mut PresetRegistry
+    {
+        &mut self.presets
+    }
+
+    /// Switches the base layer of every led to a preset, in step with the
+    /// rest of the hive
+    pub fn select_preset(&mut self, id: PresetId, params: &PresetParams) -> Result<(), PatternError>
+    {
+        let preset = self.presets.build(id, params)?;
+        self.pattern_engine.crossfade_pattern(0, preset.pattern, self.show_crossfade, Easing::Linear)?;
+        self.pattern_engine.play_pattern(0, 0, preset.spread)?;
+        self.pattern_engine.align(self.last_tick);
+        Ok(())
+    }
+
+    /// Selects a preset here and returns the command that carries it to
+    /// every neighbor
+    pub fn broadcast_preset(&mut self, id: PresetId, params: &PresetParams) -> Result<PresetCommand, PatternError>
+    {
+        let sequence = match self.preset_sequence
+        {
+            Some(sequence) => sequence.wrapping_add(1),
+            None => 0
+        };
+        self.select_preset(id, params)?;
+        self.preset_sequence = Some(sequence);
+        Ok(PresetCommand::new(id, params, PresetScope::Hive, sequence))
+    }
+
+    pub fn preset_message(&mut self, port: HardPort, command: &PresetCommand) -> Message
+    {
+        self.network.command_message(port as u8, MessageStatus::STATUS_QUERY, NetworkQuery::PRESET, command.as_bytes())
+    }
+
+    /// Applies a preset command from a neighbor. Hive wide commands not seen
+    /// before are returned, to be passed on through the other ports.
+    pub fn handle_preset_command(&mut self, msg: &Message) -> Result<Option<PresetCommand>, PatternError>
+    {
+        let command = match query_payload(msg, NetworkQuery::PRESET)
+        {
+            Some(payload) => PresetCommand::decode(payload)?,
+            None => return Err(PatternError::InvalidPatternError)
+        };
+        match command.scope()?
+        {
+            PresetScope::Cell => {
+                self.select_preset(command.id(), &command.params())?;
+                Ok(None)
+            },
+            PresetScope::Hive => {
+                // Sequences wrap, so only a step forward of under half the
+                // range is newer. Anything else was already seen, or was
+                // overtaken on a faster path.
+                if let Some(last) = self.preset_sequence
+                {
+                    if command.sequence().wrapping_sub(last) as i8 <= 0
+                    {
+                        return Ok(None);
+                    }
+                }
+                self.preset_sequence = Some(command.sequence());
+                self.select_preset(command.id(), &command.params())?;
+                Ok(Some(command))
+            }
+        }
+    }
 }
@@ -15,4 +15,16 @@ pub enum PatternError {
     PatternSizeError,
     PatternCountError,
     InvalidCursorError,
+    InvalidLayerError,
+    TruncatedDataError,
+    UnsupportedVersionError,
+    UnknownPatternIdError,
+    UnknownEasingError,
+    InvalidProgramError,
+    InvalidOpcodeError,
+    InvalidRegisterError,
+    ProgramBoundsError,
+    ProgramStackError,
+    ProgramStallError,
+    UnknownPresetError,
 }
@@ -1,13 +1,16 @@
-use hexcell_api::{messaging::{Message, MessageBuffer}, hexapi_errors::NetworkError};
+use hexcell_api::{messaging::{Message, MessageBuffer}, hexapi_errors::NetworkError, timer::Timestamp};
 use heapless::{spsc::Queue, Vec};
 use embedded_error_chain::Error;
 use embedded_time::duration::*;
 use zerocopy::AsBytes;
 use core::cell::RefCell;
-use crate::scheduler::{Scheduler, global_scheduler};
+use crate::scheduler::Scheduler;
 
 use crate::ports::{HardPort, PORT_COUNT};
 
+pub mod timesync;
+pub use timesync::{TimeSync, TimeSyncPacket, TIME_SYNC_PACKET_SIZE};
+
 pub const UID_INVALID: u32 = 0;
 
 pub enum MessageStatus
@@ -40,6 +43,16 @@ pub struct NetworkId
 
 impl NetworkId
 {
+    pub fn x(&self) -> i16
+    {
+        self.x
+    }
+
+    pub fn y(&self) -> i16
+    {
+        self.y
+    }
+
     pub fn compute_external_id(self, connected_port: HardPort) -> NetworkId
     {
         // Don't compute a UID (will be supplied by a downstream device, if any)
@@ -93,17 +106,31 @@ pub enum NetworkQuery
     ROUTETO,
     ENUMERATE,
     BROADCAST,
+    TIMESYNC,
+    PRESET,
+    SHOWDONE,
     // Must be last
     INVALID,
 }
 
+/// Body of msg after its query byte, if msg carries query
+pub fn query_payload(msg: &Message, query: NetworkQuery) -> Option<&[u8]>
+{
+    match msg.body.split_first()
+    {
+        Some((first, payload)) if *first == query as u8 => Some(payload),
+        _ => None
+    }
+}
+
 pub struct NetworkFSM
 {
     state: NetworkState,
     selected_port: HardPort,
     broadcast_counter: u8,
     message_builder: MessageBuffer,
-    id: NetworkId
+    id: NetworkId,
+    time: TimeSync,
 }
 
 impl NetworkFSM
@@ -133,22 +160,91 @@ impl NetworkFSM
             selected_port: HardPort::PORT_A,
             broadcast_counter: 0,
             message_builder: MessageBuffer::new(),
-            id
+            id,
+            time: TimeSync::new(),
+        }
+    }
+
+    pub fn id(&self) -> NetworkId
+    {
+        self.id
+    }
+
+    /// Makes this cell's clock the time reference for the hive
+    pub fn make_time_root(&mut self)
+    {
+        self.time.make_root();
+    }
+
+    /// Local time translated onto the root's clock
+    pub fn network_time(&self, now: Timestamp) -> Timestamp
+    {
+        self.time.network_time(now)
+    }
+
+    pub fn time_sync(&self) -> &TimeSync
+    {
+        &self.time
+    }
+
+    /// True once after network time has stepped
+    pub fn take_time_resynced(&mut self) -> bool
+    {
+        self.time.take_resynced()
+    }
+
+    /// Frames payload as a query message, the first body byte names the query
+    pub fn command_message(&mut self, port: u8, status: MessageStatus, query: NetworkQuery, payload: &[u8]) -> Message
+    {
+        self.message_builder.clear();
+        let _ = self.message_builder.push(query as u8);
+        for byte in payload
+        {
+            let _ = self.message_builder.push(*byte);
         }
+        Message::new(port, status as u8, &self.message_builder)
     }
 
-    pub fn schedule(&mut self, status: MessageStatus, )
+    /// Asks the neighbor on port for its network time
+    pub fn time_sync_query(&mut self, port: HardPort, now: Timestamp) -> Message
+    {
+        let packet = self.time.request(now);
+        self.command_message(port as u8, MessageStatus::STATUS_QUERY, NetworkQuery::TIMESYNC, packet.as_bytes())
+    }
+
+    /// Answers a neighbor's TIMESYNC query, or completes one of ours, where
+    /// now is the local time msg arrived. Returns the reply to send, if any.
+    pub fn handle_time_sync(&mut self, msg: &Message, now: Timestamp) -> Result<Option<Message>, Error<NetworkError>>
+    {
+        let payload = match query_payload(msg, NetworkQuery::TIMESYNC)
+        {
+            Some(payload) => payload,
+            None => return Err(Error::new(NetworkError::InvalidMessageContents))
+        };
+        let packet = TimeSyncPacket::read(payload)?;
+        if msg.header.status == MessageStatus::STATUS_QUERY as u8
+        {
+            match self.time.respond(&packet, now, now)
+            {
+                Some(reply) => Ok(Some(self.command_message(msg.header.port, MessageStatus::STATUS_OK, NetworkQuery::TIMESYNC, reply.as_bytes()))),
+                None => Ok(None)
+            }
+        }
+        else
+        {
+            self.time.complete(&packet, msg.header.port, now)?;
+            Ok(None)
+        }
+    }
 
     pub fn init(&mut self)
     {
-        self.update(MessageStatus::STATUS_OK);
-        global_scheduler().borrow_mut().queue_task(|| self.update(MessageStatus::STATUS_OK), Microseconds(0), true);
-        
+        self.update();
     }
 
     pub fn task_callback(&mut self)
     {
-        self.update(MessageStatus::STATUS_OK);
+        self.update();
     }
 
     pub fn task_timeout(&mut self)
@@ -156,7 +252,7 @@ impl NetworkFSM
         
     }
 
-    pub fn update(&mut self, response: MessagStatus)
+    pub fn update(&mut self)
     {
         match self.state
         {
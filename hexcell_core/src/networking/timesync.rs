@@ -0,0 +1,195 @@
+use hexcell_api::hexapi_errors::NetworkError;
+use hexcell_api::timer::Timestamp;
+use embedded_time::duration::*;
+use zerocopy::{AsBytes, FromBytes, FromZeroes, Unaligned};
+use zerocopy::byteorder::{LittleEndian, U64};
+
+/// Hop count of a cell that has not heard the root's clock yet
+pub const HOPS_UNSYNCED: u8 = 0xFF;
+/// Smallest change in offset, in microseconds, that counts as a step in
+/// network time. Smaller corrections are absorbed by the next frame.
+pub const RESYNC_THRESHOLD: i64 = 5_000;
+/// Local microseconds without an accepted exchange before the stored hop
+/// count is forgotten and any synced neighbor may become the source
+pub const SOURCE_TIMEOUT: u64 = 10_000_000;
+
+/// Body of a TIMESYNC exchange. The requester fills origin, the responder
+/// echoes it back alongside its own network time on receipt and on reply.
+#[repr(C)]
+#[derive(Copy, Clone, Default, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct TimeSyncPacket
+{
+    // Requester local time when the query was sent
+    origin: U64<LittleEndian>,
+    // Responder network time when the query arrived
+    receive: U64<LittleEndian>,
+    // Responder network time when the reply was sent
+    transmit: U64<LittleEndian>,
+    // Responder distance from the root
+    hops: u8,
+}
+
+pub const TIME_SYNC_PACKET_SIZE: usize = core::mem::size_of::<TimeSyncPacket>();
+
+impl TimeSyncPacket
+{
+    pub fn read(bytes: &[u8]) -> Result<TimeSyncPacket, NetworkError>
+    {
+        match TimeSyncPacket::read_from_prefix(bytes)
+        {
+            Some(packet) => Ok(packet),
+            None => Err(NetworkError::InvalidMessageContents)
+        }
+    }
+}
+
+/// Tracks this cell's offset from the root clock. The root's time spreads
+/// hop by hop: each cell syncs against whichever neighbor is closest to
+/// the root, compensating for the round trip delay of the exchange.
+#[derive(Copy, Clone)]
+pub struct TimeSync
+{
+    // Added to local time to give network time
+    offset: i64,
+    hops: u8,
+    // Port the accepted source answers on
+    parent: Option<u8>,
+    // Local time of the last accepted exchange
+    synced_at: u64,
+    // Origin of the query awaiting a reply
+    pending: Option<u64>,
+    // Round trip of the last accepted exchange, less the responder's turnaround
+    delay: u32,
+    // Network time stepped since last checked
+    resynced: bool,
+}
+
+impl Default for TimeSync
+{
+    fn default() -> TimeSync
+    {
+        TimeSync::new()
+    }
+}
+
+impl TimeSync
+{
+    pub fn new() -> TimeSync
+    {
+        TimeSync { offset: 0, hops: HOPS_UNSYNCED, parent: None, synced_at: 0, pending: None, delay: 0, resynced: false }
+    }
+
+    /// This cell's clock becomes network time
+    pub fn make_root(&mut self)
+    {
+        self.offset = 0;
+        self.hops = 0;
+        self.parent = None;
+        self.pending = None;
+        self.delay = 0;
+        self.resynced = true;
+    }
+
+    pub fn is_synced(&self) -> bool
+    {
+        self.hops != HOPS_UNSYNCED
+    }
+
+    pub fn is_root(&self) -> bool
+    {
+        self.hops == 0
+    }
+
+    pub fn hops(&self) -> u8
+    {
+        self.hops
+    }
+
+    pub fn delay(&self) -> Microseconds<u32>
+    {
+        Microseconds(self.delay)
+    }
+
+    pub fn network_time(&self, local: Timestamp) -> Timestamp
+    {
+        local.offset(self.offset)
+    }
+
+    /// True once after each step of network time, so patterns can realign
+    pub fn take_resynced(&mut self) -> bool
+    {
+        let resynced = self.resynced;
+        self.resynced = false;
+        resynced
+    }
+
+    /// Starts an exchange with a neighbor
+    pub fn request(&mut self, local: Timestamp) -> TimeSyncPacket
+    {
+        self.pending = Some(local.micros());
+        TimeSyncPacket { origin: U64::new(local.micros()), hops: HOPS_UNSYNCED, ..Default::default() }
+    }
+
+    /// Answers a neighbor's request, received and now are local times.
+    /// Unsynced cells have no time to give and stay quiet.
+    pub fn respond(&self, request: &TimeSyncPacket, received: Timestamp, now: Timestamp) -> Option<TimeSyncPacket>
+    {
+        if !self.is_synced()
+        {
+            return None;
+        }
+        Some(TimeSyncPacket {
+            origin: request.origin,
+            receive: U64::new(self.network_time(received).micros()),
+            transmit: U64::new(self.network_time(now).micros()),
+            hops: self.hops,
+        })
+    }
+
+    /// Finishes an exchange, received is the local time the reply arrived
+    /// on port
+    pub fn complete(&mut self, response: &TimeSyncPacket, port: u8, received: Timestamp) -> Result<(), NetworkError>
+    {
+        let t0 = response.origin.get();
+        if self.pending != Some(t0)
+        {
+            return Err(NetworkError::InvalidMessageContents);
+        }
+        self.pending = None;
+        if self.is_root() || response.hops == HOPS_UNSYNCED
+        {
+            return Ok(());
+        }
+        // Keep following the current source wherever it moves to, otherwise
+        // only switch to a neighbor closer to the root, unless the source
+        // has gone quiet
+        let from_parent = self.parent == Some(port);
+        let stale = received.micros().wrapping_sub(self.synced_at) > SOURCE_TIMEOUT;
+        if !from_parent && !stale && response.hops >= self.hops
+        {
+            return Ok(());
+        }
+
+        let t1 = response.receive.get() as i64;
+        let t2 = response.transmit.get() as i64;
+        let t3 = received.micros() as i64;
+        let t0 = t0 as i64;
+        // Outbound and return legs, each is offset +/- the one way delay
+        let outbound = t1 - t0;
+        let inbound = t2 - t3;
+        let offset = (outbound + inbound) / 2;
+        // Joining the network always counts as a step
+        if !self.is_synced() || (offset - self.offset).abs() > RESYNC_THRESHOLD
+        {
+            self.resynced = true;
+        }
+        self.offset = offset;
+        let round_trip = t3 - t0;
+        let turnaround = t2 - t1;
+        self.delay = (round_trip - turnaround).clamp(0, u32::MAX as i64) as u32;
+        self.hops = response.hops.saturating_add(1).min(HOPS_UNSYNCED - 1);
+        self.parent = Some(port);
+        self.synced_at = received.micros();
+        Ok(())
+    }
+}
@@ -1,14 +1,61 @@
 
 use embedded_time::duration::*;
-use hexcell_api::display::{Led, LED_COUNT, LedBuffer};
-use heapless::Vec;
+use hexcell_api::display::{Hsv, Led, LED_COUNT, LED_GEOMETRY, LedBuffer, LedPosition, WideBuffer, WideLed, EDGE_COUNT, WIDE_MAX};
+use hexcell_api::timer::Timestamp;
+use heapless::{Deque, Vec};
 use crate::hexcore_errors::PatternError;
+
+pub mod easing;
+#[cfg(feature = "std")]
+pub mod dsl;
+pub mod encoding;
+pub mod generators;
+pub mod playback;
+pub mod presets;
+pub mod spatial;
+pub mod timeline;
+pub mod vm;
+
+use easing::Easing;
+use generators::Rng;
+use playback::Playback;
+use timeline::{Timeline, TimelineReader, MAX_TIMELINE_COUNT};
+use vm::{Program, VmAction, VmState, MAX_PROGRAM_COUNT};
+
 /// Arbitrary, reduce if necessary
 pub const MAX_PATTERN_ELEMENTS: usize = 16;
-/// One pattern per led (reduce if necessary)
-pub const MAX_PATTERN_COUNT: usize = LED_COUNT;
+/// Pattern slots, shared by cursors so this need not grow with the led count
+pub const MAX_PATTERN_COUNT: usize = 9;
+/// Base layer plus overlays (each costs a full set of cursors)
+pub const MAX_LAYERS: usize = 3;
+/// Transient patterns that can play at once, the oldest is dropped for a new one
+pub const MAX_OVERRIDES: usize = 2;
+/// Cursor events held until polled, the oldest are dropped beyond this.
+/// Room for one from every cursor of every layer, so a tick's worth is never
+/// cut short.
+pub const MAX_PATTERN_EVENTS: usize = MAX_LAYERS * LED_COUNT;
+
+pub use hexcell_api::display::{LedMask, ALL_LEDS};
 
 const OFF: Led = Led { r: 0, g: 0, b: 0 };
+const WIDE_OFF: WideLed = WideLed::widen(OFF);
+
+/// Heartbeat envelope as (phase, level) keypoints, phase in 1/256ths of the element duration.
+/// A full "lub" followed by a softer "dub", then rest until the element ends.
+const HEARTBEAT_ENVELOPE: [(u8, u8); 6] = [
+    (0, 0),
+    (32, 255),
+    (64, 0),
+    (96, 160),
+    (128, 0),
+    (255, 0),
+];
+
+/// SOS in Morse units: dot = 1 on, dash = 3 on, 1 unit between symbols,
+/// 3 units between letters and a 7 unit word gap before the sequence repeats
+const SOS_UNITS: u32 = 34;
+/// '1' means the led is lit during that unit
+const SOS_SEQUENCE: &[u8; SOS_UNITS as usize] = b"1010100011101110111000101010000000";
 
 /// Encoding of a pattern identifyer
 #[derive(Copy, Clone, Default)]
@@ -21,6 +68,41 @@ pub enum PatternId
     Fade,
     Heartbeat,
     SOS,
+    /// Rotates hue from the previous color the short way around the wheel
+    HueRotate,
+    /// Rotates hue from the previous color the long way around the wheel
+    HueRotateLong,
+    /// One full turn of the hue wheel, starting and ending at color
+    Rainbow,
+    /// A pulse of color travelling along the x axis of the hive, param is the
+    /// phase shift per cell in 256ths of the element duration
+    WaveX,
+    /// As WaveX, along the y axis
+    WaveY,
+    /// Rings of color spreading out from the root cell
+    Radial,
+    /// A single arm turning around the root cell, param tightens the spiral
+    Spiral,
+    /// Wanders smoothly between the previous color and color, param is the
+    /// number of random control points per element
+    Noise,
+    /// Flickers like a flame in color, param speeds up the flicker
+    Fire,
+    /// Leds swell to color at random over the previous color, param is the
+    /// number of chances per element
+    Twinkle,
+    /// Brief random flashes of color over the previous color, param is the
+    /// number of chances per element
+    Sparkle,
+    /// A pulse of color running from the middle of the cell out to its rim,
+    /// param is the phase shift from center to rim (128 if zero)
+    Sweep,
+    /// Arms of color turning clockwise around the cell, param is the number
+    /// of arms (one if zero)
+    Rotate,
+    /// Brings in color across the cell from the edge param % 6, leaving the
+    /// previous color on the opposite side
+    EdgeGradient,
 }
 
 /// A single pattern element: a color and duration
@@ -29,7 +111,11 @@ pub struct PatternElement
 {
     pub pattern: PatternId,
     pub color: Led,
-    pub duration: Microseconds<u32>
+    pub duration: Microseconds<u32>,
+    /// Curve applied to transitions (Fade)
+    pub easing: Easing,
+    /// Meaning depends on pattern, e.g. the per cell shift of spatial patterns
+    pub param: u8,
 }
 
 #[derive(Clone, Default)]
@@ -56,6 +142,23 @@ impl Pattern
             Err(t) => Err(PatternError::PatternSizeError)
         }
     }
+
+    /// Number of elements
+    pub fn len(&self) -> usize
+    {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.data.is_empty()
+    }
+
+    /// Total length of one pass through the pattern, saturating at u32::MAX
+    pub fn duration(&self) -> Microseconds<u32>
+    {
+        Microseconds(self.data.iter().fold(0u32, |total, element| total.saturating_add(element.duration.integer())))
+    }
 }
 
 pub struct PatternBuilder
@@ -88,155 +191,1352 @@ impl PatternBuilder
     }
 }
 
-/// A stateful index into a pattern
+/// What an element may depend on besides time
+#[derive(Copy, Clone, Default)]
+struct RenderContext
+{
+    // Position of this cell in the hive
+    x: i16,
+    y: i16,
+    // Random stream, seeded per cell then split per led and element
+    rng: Rng,
+    // Where the led being drawn sits within the cell
+    led: LedPosition,
+}
+
+impl RenderContext
+{
+    /// The context for drawing the led at index, with its own random stream
+    fn for_led(&self, index: usize) -> RenderContext
+    {
+        RenderContext { rng: self.rng.stream(index as u32), led: LED_GEOMETRY[index], ..*self }
+    }
+}
+
+/// Everything a cursor needs for one frame besides the patterns it plays
+#[derive(Copy, Clone)]
+struct FrameInput
+{
+    context: RenderContext,
+    // Combined with the cursor's own settings
+    playback: Playback,
+    // Time since the last frame
+    delta: u32,
+    // Raised since the last frame, for programs waiting on them
+    events: u8,
+}
+
+/// A stateful index into a pattern or program
 #[derive(Copy, Clone, Default)]
 pub struct PatternCursor
 {
     // Position with
     element_index: usize,
     pattern_index: usize,
+    // When set, elements come from this program rather than pattern_index
+    program: Option<usize>,
+    vm: VmState,
+    // When set, colors come straight from this recorded timeline instead
+    timeline: Option<usize>,
+    reader: TimelineReader,
     // Holds the last color value, for blending purposes
     input_buffer: Led,
     elapsed: u32,
+    // Elements completed since the start, so procedural elements vary every pass
+    played: u32,
+    // Time offset into the pattern, applied whenever the cursor is (re)started
+    phase: u32,
+    playback: Playback,
+    // Turned around by ping-pong, so heading the other way to playback.reverse
+    bounced: bool,
+    // Fraction of a microsecond left over by speed scaling
+    remainder: u8,
     auto_restart: bool,
     enabled: bool
 }
 
-/// Pattern Engine
-#[derive(Default)]
-pub struct PatternEngine
+impl PatternCursor
 {
-    cursors: [PatternCursor; LED_COUNT],
-    patterns: Vec<Pattern, MAX_PATTERN_COUNT>,
-    output: LedBuffer,
-}
+    /// Moves the cursor to offset (wrapped to one pass) from the start of pattern
+    fn seek(&mut self, pattern: &Pattern, offset: u64)
+    {
+        self.element_index = 0;
+        self.elapsed = 0;
+        self.played = 0;
+        self.input_buffer = OFF;
+        let total = pattern.duration().integer();
+        if total == 0
+        {
+            return;
+        }
+        let passes = (offset / total as u64) as u32;
+        let mut remaining = (offset % total as u64) as u32;
+        for (index, element) in pattern.data.iter().enumerate()
+        {
+            if remaining < element.duration.integer()
+            {
+                self.element_index = index;
+                self.elapsed = remaining;
+                self.played = passes.wrapping_mul(pattern.data.len() as u32).wrapping_add(index as u32);
+                return;
+            }
+            remaining -= element.duration.integer();
+            self.input_buffer = element.color;
+        }
+    }
 
-impl PatternEngine
-{
-    pub fn new() -> PatternEngine
+    /// Time from the start of the pass
+    fn position(&self, pattern: &Pattern) -> u64
     {
-        let mut init = Vec::<Pattern, MAX_PATTERN_COUNT>::new();
-        for _ in 0..MAX_PATTERN_COUNT
+        let before: u64 = pattern.data.iter().take(self.element_index).map(|e| e.duration.integer() as u64).sum();
+        before + self.elapsed as u64
+    }
+
+    /// Jumps to offset from the start of whatever the cursor plays,
+    /// restarting a one-shot that had finished
+    fn seek_to(&mut self, patterns: &[Pattern], timelines: &[Timeline], offset: u64) -> Result<(), PatternError>
+    {
+        if let Some(timeline_idx) = self.timeline
         {
-            init.push(Pattern::default());
+            self.reader.seek(&timelines[timeline_idx], offset);
+        }
+        else if self.program.is_some()
+        {
+            return Err(PatternError::InvalidProgramError);
         }
+        else
+        {
+            self.seek(&patterns[self.pattern_index], offset);
+        }
+        self.bounced = false;
+        Ok(())
+    }
 
-        PatternEngine {
-            cursors: [PatternCursor::default(); LED_COUNT],
-            patterns: init,
-            output: LedBuffer::default()
+    /// Offset of the start of an element, or of a frame for timelines
+    fn element_offset(&self, patterns: &[Pattern], timelines: &[Timeline], element_idx: usize) -> Result<u64, PatternError>
+    {
+        if let Some(timeline_idx) = self.timeline
+        {
+            let timeline = &timelines[timeline_idx];
+            if element_idx >= timeline.frames() as usize
+            {
+                return Err(PatternError::InvalidPatternError);
+            }
+            return Ok(element_idx as u64 * timeline.frame_period().integer() as u64);
+        }
+        if self.program.is_some()
+        {
+            return Err(PatternError::InvalidProgramError);
+        }
+        let pattern = &patterns[self.pattern_index];
+        if element_idx >= pattern.data.len()
+        {
+            return Err(PatternError::InvalidPatternError);
         }
+        Ok(pattern.data.iter().take(element_idx).map(|e| e.duration.integer() as u64).sum())
     }
 
-    pub fn start(&mut self)
+    /// Element being played, if any
+    fn current(&self, patterns: &[Pattern]) -> Option<PatternElement>
     {
-        for cursor in self.cursors.iter_mut()
+        if self.program.is_some() && !self.vm.calling
         {
-            cursor.enabled = true;
-            cursor.pattern_index = 0;
-            cursor.element_index = 0;
-            cursor.input_buffer = OFF;
+            return self.vm.element;
         }
+        patterns.get(self.pattern_index)?.data.get(self.element_index).copied()
     }
 
-    pub fn run(&mut self, delta: Microseconds<u32>) -> LedBuffer
+    fn restart_program(&mut self)
+    {
+        self.vm.reset();
+        self.element_index = 0;
+        self.elapsed = 0;
+        self.played = 0;
+        self.input_buffer = OFF;
+    }
+
+    fn fault(&mut self, error: PatternError)
+    {
+        self.vm.fault = Some(error);
+        self.enabled = false;
+    }
+
+    /// Runs the program until it yields something to play, waits or halts
+    fn fetch(&mut self, patterns: &[Pattern], program: &Program)
     {
-        for (index, cursor) in self.cursors.iter_mut().enumerate()
+        self.vm.element = None;
+        self.vm.calling = false;
+        let mut restarted = false;
+        loop
         {
-            if cursor.enabled
+            match self.vm.step(program)
             {
-                cursor.elapsed += delta.integer();
-                let pattern: &Pattern = &self.patterns[cursor.pattern_index];
-                let current_element: &PatternElement = &pattern.data[cursor.element_index];
-                if cursor.elapsed > current_element.duration.integer()
-                {
-                    cursor.elapsed = current_element.duration.integer();
-                }
-                match current_element.pattern
-                {
-                    PatternId::Solid => {
-                        // Copy color to output
-                        self.output[index] = current_element.color
-                    },
-                    PatternId::Blink => {
-                        // Copy color or OFF to buffer
-                        if cursor.elapsed < (current_element.duration.integer() >> 1)
-                        {
-                            self.output[index] = OFF;
-                        }
-                        else
-                        {
-                            self.output[index] = current_element.color
-                        }
-                    },
-                    PatternId::Fade => {
-                        self.output[index] = cursor.input_buffer.interpolate(current_element.color, cursor.elapsed, current_element.duration.integer());
-                    },
-                    PatternId::Heartbeat => {},
-                    PatternId::SOS => {},
-                    _ => {}
-                }
-
-                if cursor.elapsed >= current_element.duration.integer()
-                {
-                    cursor.element_index += 1;
-                    if cursor.element_index >= pattern.data.len()
+                Ok(VmAction::Play(element)) => {
+                    self.vm.element = Some(element);
+                    return;
+                },
+                Ok(VmAction::Call(pattern_idx)) => {
+                    match patterns.get(pattern_idx)
                     {
-                        cursor.element_index = 0;
-                        if cursor.auto_restart
-                        {
-                            cursor.enabled = true;
-                        }
-                        else
-                        {
-                            cursor.enabled = false;
-                        }
+                        Some(pattern) if !pattern.data.is_empty() => {
+                            self.vm.calling = true;
+                            self.pattern_index = pattern_idx;
+                            self.element_index = 0;
+                        },
+                        _ => self.fault(PatternError::InvalidPatternError)
+                    }
+                    return;
+                },
+                Ok(VmAction::Wait(mask)) => {
+                    self.vm.wait_mask = mask;
+                    return;
+                },
+                Ok(VmAction::Halt) => {
+                    // A program that halts again straight after restarting has nothing to play
+                    if self.auto_restart && !restarted
+                    {
+                        restarted = true;
+                        self.vm.reset();
                     }
-                    if cursor.enabled
+                    else
                     {
-                        cursor.input_buffer = current_element.color;
-                        cursor.elapsed = 0;
+                        self.enabled = false;
+                        return;
                     }
+                },
+                Err(error) => {
+                    self.fault(error);
+                    return;
                 }
             }
         }
-        self.output
     }
 
-    pub fn get_output_buffer(&self) -> LedBuffer
+    /// Moves on to the element after the current one
+    fn advance(&mut self, patterns: &[Pattern], programs: &[Program])
     {
-        self.output
+        match self.program
+        {
+            Some(program_idx) if !self.vm.calling => {
+                self.fetch(patterns, &programs[program_idx]);
+            },
+            Some(program_idx) => {
+                // Return to the program once the called pattern completes
+                self.element_index += 1;
+                if self.element_index >= patterns[self.pattern_index].data.len()
+                {
+                    self.element_index = 0;
+                    self.fetch(patterns, &programs[program_idx]);
+                }
+            },
+            None => {
+                self.element_index += 1;
+                if self.element_index >= patterns[self.pattern_index].data.len()
+                {
+                    self.element_index = 0;
+                    self.enabled = self.auto_restart;
+                }
+            }
+        }
     }
 
-    pub fn set_pattern(&mut self, at: usize, pattern: Pattern)
+    /// Moves by delta along the pass in any direction, turning around for
+    /// ping-pong. Returns where to go, or None once a one-shot has finished.
+    fn travel(&mut self, position: u64, total: u64, delta: u32, playback: &Playback) -> Option<u64>
     {
-        self.patterns[at] = pattern;
+        let backwards = self.heading(playback);
+        let (position, heading) = playback::travel(position, total, delta as u64, backwards, playback.ping_pong, self.auto_restart)?;
+        self.bounced = heading != playback.reverse;
+        Some(position)
     }
 
-    pub fn set_cursor_to_pattern(&mut self, cursor_idx: usize, pattern_idx: usize, restart: bool) -> Result<(), PatternError>
+    /// Advances by the frame's delta and writes the current color to output,
+    /// which is left holding its last value while the cursor is disabled or
+    /// waiting. Playback settings are combined with the frame's first,
+    /// programs can only be paused or sped up. Returns what the cursor did,
+    /// if anything.
+    fn run(&mut self, patterns: &[Pattern], programs: &[Program], timelines: &[Timeline], frame: &FrameInput, output: &mut WideLed) -> Option<CursorEvent>
     {
-        if cursor_idx < MAX_PATTERN_COUNT
+        if !self.enabled
+        {
+            return None;
+        }
+        let (context, events) = (&frame.context, frame.events);
+        let playback = self.playback.under(&frame.playback);
+        if !playback.ping_pong
+        {
+            self.bounced = false;
+        }
+        let delta = if playback.paused { 0 } else { playback::scale(frame.delta, playback.speed, &mut self.remainder) };
+        // Plain forward play keeps to the cheaper incremental path below
+        let travelling = playback.reverse != self.bounced || playback.ping_pong;
+        if let Some(timeline_idx) = self.timeline
         {
-            if let Some(cursor) = self.cursors.get_mut(cursor_idx)
+            let timeline = &timelines[timeline_idx];
+            // Frames are too fine grained to report, only whole passes are
+            let (before, heading) = (self.reader.position(timeline), self.heading(&playback));
+            if travelling
             {
-                if pattern_idx < self.patterns.len()
+                match self.travel(before, timeline.duration().integer(), delta, &playback)
                 {
-                    cursor.pattern_index = pattern_idx;
-                    cursor.auto_restart = restart;
-                    Ok(())
+                    Some(position) => self.reader.seek(timeline, position),
+                    None => {
+                        self.reader.restart(timeline);
+                        self.enabled = false;
+                    }
                 }
-                else {
-                    Err(PatternError::InvalidPatternError)
+            }
+            else
+            {
+                self.enabled = self.reader.advance(timeline, delta, self.auto_restart);
+            }
+            *output = self.reader.color().into();
+            return if !self.enabled
+            {
+                Some(CursorEvent::Finished)
+            }
+            else if self.wrapped(before, self.reader.position(timeline), heading, &playback)
+            {
+                Some(CursorEvent::Looped)
+            }
+            else
+            {
+                None
+            };
+        }
+        if travelling && self.program.is_none()
+        {
+            let pattern = &patterns[self.pattern_index];
+            let (before, heading, element_before) = (self.position(pattern), self.heading(&playback), self.element_index);
+            match self.travel(before, pattern.duration().integer() as u64, delta, &playback)
+            {
+                Some(position) => self.seek(pattern, position),
+                None => {
+                    self.seek(pattern, 0);
+                    self.enabled = false;
                 }
             }
+            if let Some(element) = self.current(patterns)
+            {
+                let context = RenderContext { rng: context.rng.stream(self.played), ..*context };
+                *output = render(&element, &context, self.input_buffer, self.elapsed);
+            }
+            return if !self.enabled
+            {
+                Some(CursorEvent::Finished)
+            }
+            else if self.wrapped(before, self.position(pattern), heading, &playback)
+            {
+                Some(CursorEvent::Looped)
+            }
+            else if self.element_index != element_before
+            {
+                Some(CursorEvent::Element(self.element_index))
+            }
             else
             {
-                Err(PatternError::InvalidCursorError)
+                None
+            };
+        }
+        if let Some(program_idx) = self.program
+        {
+            if self.vm.wait_mask != 0
+            {
+                if self.vm.wait_mask & events == 0
+                {
+                    return None;
+                }
+                self.vm.wait_mask = 0;
+                self.elapsed = 0;
+                self.fetch(patterns, &programs[program_idx]);
+            }
+            else if self.current(patterns).is_none()
+            {
+                self.fetch(patterns, &programs[program_idx]);
+            }
+            if !self.enabled
+            {
+                return Some(CursorEvent::Finished);
+            }
+            if self.vm.wait_mask != 0
+            {
+                return None;
             }
         }
-        else
+        let current_element = self.current(patterns)?;
+        self.elapsed = self.elapsed.saturating_add(delta);
+        // Time past the end of this element carries into the next, so
+        // cursors with different phases stay the same distance apart
+        let overflow = self.elapsed.saturating_sub(current_element.duration.integer());
+        if self.elapsed > current_element.duration.integer()
         {
-            Err(PatternError::InvalidCursorError)
+            self.elapsed = current_element.duration.integer();
+        }
+        let context = RenderContext { rng: context.rng.stream(self.played), ..*context };
+        *output = render(&current_element, &context, self.input_buffer, self.elapsed);
+
+        if self.elapsed >= current_element.duration.integer()
+        {
+            self.played = self.played.wrapping_add(1);
+            self.advance(patterns, programs);
+            if !self.enabled
+            {
+                return Some(CursorEvent::Finished);
+            }
+            self.input_buffer = current_element.color;
+            self.elapsed = overflow;
+            if self.program.is_none() && self.element_index == 0
+            {
+                return Some(CursorEvent::Looped);
+            }
+            return Some(CursorEvent::Element(self.element_index));
+        }
+        None
+    }
+
+    /// Whether the cursor is playing backwards
+    fn heading(&self, playback: &Playback) -> bool
+    {
+        playback.reverse != self.bounced
+    }
+
+    /// Whether moving from before to after went past the end of a pass (the
+    /// start, in reverse) and round again, rather than turning around
+    fn wrapped(&self, before: u64, after: u64, heading: bool, playback: &Playback) -> bool
+    {
+        if self.heading(playback) != heading
+        {
+            return false;
+        }
+        if heading { after > before } else { after < before }
+    }
+}
+
+/// What a cursor did during a run
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CursorEvent
+{
+    /// Moved on to the element at this index of its pattern. Elements a
+    /// program plays directly all count as index 0.
+    Element(usize),
+    /// Finished a pass and started over
+    Looped,
+    /// Reached the end of a one-shot, or its program halted or faulted
+    Finished,
+}
+
+/// A cursor event and the cursor it happened to
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PatternEvent
+{
+    pub layer: usize,
+    pub cursor: usize,
+    pub event: CursorEvent,
+}
+
+/// How a layer combines with the layers beneath it
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum BlendMode
+{
+    /// Covers everything below
+    #[default]
+    Replace,
+    /// Saturating sum of both layers
+    Add,
+    /// Darkens the layers below, white leaves them unchanged
+    Multiply,
+    /// Brightest of each channel
+    Max,
+    /// Mixes over the layers below with the given opacity (0xFF is opaque)
+    Alpha(u8),
+}
+
+impl BlendMode
+{
+    pub fn apply(&self, base: WideLed, top: WideLed) -> WideLed
+    {
+        match self
+        {
+            BlendMode::Replace => top,
+            BlendMode::Add => base.saturating_add(top),
+            BlendMode::Multiply => base.modulate(top),
+            BlendMode::Max => base.max(top),
+            BlendMode::Alpha(opacity) => base.blend_linear(top, wide(*opacity)),
+        }
+    }
+}
+
+/// A full set of cursors (one per led) composited over the layers beneath it
+#[derive(Copy, Clone)]
+struct PatternLayer
+{
+    cursors: [PatternCursor; LED_COUNT],
+    output: WideBuffer,
+    blend: BlendMode,
+    enabled: bool
+}
+
+// Arrays only derive Default up to 32 entries, larger cells have more leds
+impl Default for PatternLayer
+{
+    fn default() -> PatternLayer
+    {
+        PatternLayer {
+            cursors: [PatternCursor::default(); LED_COUNT],
+            output: [WIDE_OFF; LED_COUNT],
+            blend: BlendMode::default(),
+            enabled: false
+        }
+    }
+}
+
+/// Blends from a snapshot of the leds into whatever the layers draw next
+#[derive(Copy, Clone)]
+struct Crossfade
+{
+    from: WideBuffer,
+    elapsed: u32,
+    duration: u32,
+    easing: Easing,
+}
+
+/// A one-shot pattern shown on top of everything else for some leds, the
+/// cursors underneath are paused until it completes
+#[derive(Clone)]
+struct PatternOverride
+{
+    pattern: Pattern,
+    // Only those in mask are run
+    cursors: [PatternCursor; LED_COUNT],
+    output: WideBuffer,
+    mask: LedMask
+}
+
+impl PatternOverride
+{
+    fn is_playing(&self) -> bool
+    {
+        self.cursors.iter().enumerate().any(|(index, cursor)| self.mask & (1 << index) != 0 && cursor.enabled)
+    }
+}
+
+/// Pattern Engine
+pub struct PatternEngine
+{
+    layers: [PatternLayer; MAX_LAYERS],
+    overrides: Vec<PatternOverride, MAX_OVERRIDES>,
+    patterns: Vec<Pattern, MAX_PATTERN_COUNT>,
+    programs: Vec<Program, MAX_PROGRAM_COUNT>,
+    timelines: Vec<Timeline, MAX_TIMELINE_COUNT>,
+    // Raised since the last run, for programs waiting on them
+    events: u8,
+    // Applied on top of every cursor's own playback settings
+    playback: Playback,
+    // Reported by cursors, waiting to be polled
+    pattern_events: Deque<PatternEvent, MAX_PATTERN_EVENTS>,
+    // A base layer cursor finished, kept apart from pattern_events so it
+    // can't be dropped
+    base_finished: bool,
+    crossfade: Option<Crossfade>,
+    context: RenderContext,
+    // The frame as drawn, before quantizing to output. Dithering waits for
+    // the display, after it has corrected the frame.
+    frame: WideBuffer,
+    // The frame before overrides were drawn over it, where crossfades start
+    show: WideBuffer,
+    output: LedBuffer,
+}
+
+impl Default for PatternEngine
+{
+    fn default() -> PatternEngine
+    {
+        PatternEngine::new()
+    }
+}
+
+impl PatternEngine
+{
+    pub fn new() -> PatternEngine
+    {
+        let mut init = Vec::<Pattern, MAX_PATTERN_COUNT>::new();
+        for _ in 0..MAX_PATTERN_COUNT
+        {
+            init.push(Pattern::default());
+        }
+
+        let mut programs = Vec::<Program, MAX_PROGRAM_COUNT>::new();
+        for _ in 0..MAX_PROGRAM_COUNT
+        {
+            let _ = programs.push(Program::default());
+        }
+
+        let mut timelines = Vec::<Timeline, MAX_TIMELINE_COUNT>::new();
+        for _ in 0..MAX_TIMELINE_COUNT
+        {
+            let _ = timelines.push(Timeline::default());
+        }
+
+        let mut layers = [PatternLayer::default(); MAX_LAYERS];
+        // The base layer is always drawn unless explicitly disabled
+        layers[0].enabled = true;
+
+        PatternEngine {
+            layers,
+            overrides: Vec::new(),
+            patterns: init,
+            programs,
+            timelines,
+            events: 0,
+            playback: Playback::default(),
+            pattern_events: Deque::new(),
+            base_finished: false,
+            crossfade: None,
+            context: RenderContext::default(),
+            frame: [WIDE_OFF; LED_COUNT],
+            show: [WIDE_OFF; LED_COUNT],
+            output: [OFF; LED_COUNT]
+        }
+    }
+
+    pub fn start(&mut self)
+    {
+        for layer in self.layers.iter_mut()
+        {
+            for cursor in layer.cursors.iter_mut()
+            {
+                cursor.enabled = true;
+                if let Some(timeline_idx) = cursor.timeline
+                {
+                    cursor.reader.seek(&self.timelines[timeline_idx], cursor.phase as u64);
+                }
+                else if cursor.program.is_some()
+                {
+                    cursor.restart_program();
+                }
+                else
+                {
+                    cursor.pattern_index = 0;
+                    cursor.seek(&self.patterns[0], cursor.phase as u64);
+                }
+            }
         }
     }
+
+    pub fn run(&mut self, delta: Microseconds<u32>) -> LedBuffer
+    {
+        self.frame = [WIDE_OFF; LED_COUNT];
+        let paused = self.overrides.iter().fold(0, |mask, o| mask | o.mask);
+        for (layer_idx, layer) in self.layers.iter_mut().enumerate()
+        {
+            if !layer.enabled
+            {
+                continue;
+            }
+            for (index, cursor) in layer.cursors.iter_mut().enumerate()
+            {
+                if paused & (1 << index) == 0
+                {
+                    let frame = FrameInput { context: self.context.for_led(index), playback: self.playback, delta: delta.integer(), events: self.events };
+                    if let Some(event) = cursor.run(&self.patterns, &self.programs, &self.timelines, &frame, &mut layer.output[index])
+                    {
+                        self.base_finished |= layer_idx == 0 && event == CursorEvent::Finished;
+                        // Keep the newest if nobody is polling
+                        if self.pattern_events.is_full()
+                        {
+                            self.pattern_events.pop_front();
+                        }
+                        let _ = self.pattern_events.push_back(PatternEvent { layer: layer_idx, cursor: index, event });
+                    }
+                }
+                self.frame[index] = layer.blend.apply(self.frame[index], layer.output[index]);
+            }
+        }
+        if let Some(fade) = self.crossfade.as_mut()
+        {
+            fade.elapsed = fade.elapsed.saturating_add(delta.integer());
+            if fade.elapsed >= fade.duration
+            {
+                self.crossfade = None;
+            }
+            else
+            {
+                let factor = fade.easing.apply_wide(wide_phase(fade.elapsed, fade.duration));
+                for (led, from) in self.frame.iter_mut().zip(fade.from.iter())
+                {
+                    *led = from.blend_linear(*led, factor);
+                }
+            }
+        }
+        self.show = self.frame;
+        for o in self.overrides.iter_mut()
+        {
+            for (index, cursor) in o.cursors.iter_mut().enumerate()
+            {
+                if o.mask & (1 << index) == 0
+                {
+                    continue;
+                }
+                // Overrides are feedback, not part of the show, so always play normally
+                let frame = FrameInput { context: self.context.for_led(index), playback: Playback::default(), delta: delta.integer(), events: self.events };
+                let _ = cursor.run(core::slice::from_ref(&o.pattern), &[], &[], &frame, &mut o.output[index]);
+                self.frame[index] = o.output[index];
+            }
+        }
+        self.overrides.retain(|o| o.is_playing());
+        self.events = 0;
+        for (led, wide) in self.output.iter_mut().zip(self.frame.iter())
+        {
+            *led = wide.narrow();
+        }
+        self.output
+    }
+
+    pub fn get_output_buffer(&self) -> LedBuffer
+    {
+        self.output
+    }
+
+    /// The last frame at full precision, for Display::corrected_wide to
+    /// correct and dither
+    pub fn get_wide_output_buffer(&self) -> WideBuffer
+    {
+        self.frame
+    }
+
+    /// Plays pattern once on the leds in mask, pausing whatever they were
+    /// showing and resuming it where it left off afterwards
+    pub fn play_override(&mut self, mask: LedMask, pattern: Pattern)
+    {
+        let mask = mask & ALL_LEDS;
+        if mask == 0 || pattern.data.is_empty()
+        {
+            return;
+        }
+        let cursor = PatternCursor { enabled: true, ..Default::default() };
+        let o = PatternOverride { pattern, cursors: [cursor; LED_COUNT], output: [WIDE_OFF; LED_COUNT], mask };
+        if self.overrides.is_full()
+        {
+            self.overrides.remove(0);
+        }
+        let _ = self.overrides.push(o);
+    }
+
+    /// Where this cell sits in the hive, for spatial patterns
+    pub fn set_position(&mut self, x: i16, y: i16)
+    {
+        self.context.x = x;
+        self.context.y = y;
+    }
+
+    /// Seeds procedural elements, normally with the cell's uid so every
+    /// cell looks different while staying reproducible
+    pub fn set_seed(&mut self, seed: u32)
+    {
+        self.context.rng = Rng::new(seed);
+    }
+
+    /// Drops any transient patterns, resuming the leds beneath them
+    pub fn clear_overrides(&mut self)
+    {
+        self.overrides.clear();
+    }
+
+    /// Replaces a pattern, restarting every cursor playing it from its phase
+    /// so none is left pointing past the end of a shorter one
+    pub fn set_pattern(&mut self, at: usize, pattern: Pattern) -> Result<(), PatternError>
+    {
+        match self.patterns.get_mut(at)
+        {
+            Some(slot) => *slot = pattern,
+            None => return Err(PatternError::PatternCountError)
+        }
+        let pattern = &self.patterns[at];
+        for layer in self.layers.iter_mut()
+        {
+            for cursor in layer.cursors.iter_mut()
+            {
+                // A program calling into it just returns early if its
+                // element is gone
+                if cursor.pattern_index != at || cursor.timeline.is_some() || cursor.program.is_some()
+                {
+                    continue;
+                }
+                cursor.seek(pattern, cursor.phase as u64);
+                cursor.bounced = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fades from what the layers show now into whatever is drawn over the
+    /// next duration, so any change made after this call blends in smoothly.
+    /// Overrides are left out, they are feedback rather than part of the show.
+    pub fn begin_crossfade(&mut self, duration: Microseconds<u32>, easing: Easing)
+    {
+        if duration.integer() == 0
+        {
+            self.crossfade = None;
+            return;
+        }
+        self.crossfade = Some(Crossfade { from: self.show, elapsed: 0, duration: duration.integer(), easing });
+    }
+
+    /// Replaces a pattern, crossfading into it from the current output
+    pub fn crossfade_pattern(&mut self, at: usize, pattern: Pattern, duration: Microseconds<u32>, easing: Easing) -> Result<(), PatternError>
+    {
+        if at >= self.patterns.len()
+        {
+            return Err(PatternError::PatternCountError);
+        }
+        self.begin_crossfade(duration, easing);
+        self.set_pattern(at, pattern)
+    }
+
+    pub fn is_crossfading(&self) -> bool
+    {
+        self.crossfade.is_some()
+    }
+
+    fn layer_mut(&mut self, layer_idx: usize) -> Result<&mut PatternLayer, PatternError>
+    {
+        match self.layers.get_mut(layer_idx)
+        {
+            Some(layer) => Ok(layer),
+            None => Err(PatternError::InvalidLayerError)
+        }
+    }
+
+    /// Shows or hides a layer, hidden layers are not advanced
+    pub fn enable_layer(&mut self, layer_idx: usize, enabled: bool) -> Result<(), PatternError>
+    {
+        self.layer_mut(layer_idx)?.enabled = enabled;
+        Ok(())
+    }
+
+    pub fn set_layer_blend(&mut self, layer_idx: usize, blend: BlendMode) -> Result<(), PatternError>
+    {
+        self.layer_mut(layer_idx)?.blend = blend;
+        Ok(())
+    }
+
+    pub fn set_cursor_to_pattern(&mut self, cursor_idx: usize, pattern_idx: usize, restart: bool) -> Result<(), PatternError>
+    {
+        self.set_layer_cursor_to_pattern(0, cursor_idx, pattern_idx, restart)
+    }
+
+    pub fn set_layer_cursor_to_pattern(&mut self, layer_idx: usize, cursor_idx: usize, pattern_idx: usize, restart: bool) -> Result<(), PatternError>
+    {
+        if pattern_idx >= self.patterns.len()
+        {
+            return Err(PatternError::InvalidPatternError);
+        }
+        if let Some(cursor) = self.layer_mut(layer_idx)?.cursors.get_mut(cursor_idx)
+        {
+            cursor.pattern_index = pattern_idx;
+            cursor.program = None;
+            cursor.timeline = None;
+            cursor.auto_restart = restart;
+            Ok(())
+        }
+        else
+        {
+            Err(PatternError::InvalidCursorError)
+        }
+    }
+
+    /// Replaces a program, restarting every cursor running it
+    pub fn set_program(&mut self, at: usize, program: Program) -> Result<(), PatternError>
+    {
+        match self.programs.get_mut(at)
+        {
+            Some(slot) => *slot = program,
+            None => return Err(PatternError::InvalidProgramError)
+        }
+        for layer in self.layers.iter_mut()
+        {
+            for cursor in layer.cursors.iter_mut()
+            {
+                if cursor.program == Some(at)
+                {
+                    cursor.restart_program();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_cursor_to_program(&mut self, cursor_idx: usize, program_idx: usize, restart: bool) -> Result<(), PatternError>
+    {
+        self.set_layer_cursor_to_program(0, cursor_idx, program_idx, restart)
+    }
+
+    /// Runs a program on a cursor from its first instruction
+    pub fn set_layer_cursor_to_program(&mut self, layer_idx: usize, cursor_idx: usize, program_idx: usize, restart: bool) -> Result<(), PatternError>
+    {
+        if program_idx >= self.programs.len()
+        {
+            return Err(PatternError::InvalidProgramError);
+        }
+        if let Some(cursor) = self.layer_mut(layer_idx)?.cursors.get_mut(cursor_idx)
+        {
+            cursor.program = Some(program_idx);
+            cursor.timeline = None;
+            cursor.auto_restart = restart;
+            // Distinct random branches per cursor
+            cursor.vm = VmState::new((((layer_idx * LED_COUNT) + cursor_idx + 1) as u32).wrapping_mul(0x9E37_79B9));
+            cursor.restart_program();
+            Ok(())
+        }
+        else
+        {
+            Err(PatternError::InvalidCursorError)
+        }
+    }
+
+    /// Replaces a timeline, restarting every cursor playing it
+    pub fn set_timeline(&mut self, at: usize, timeline: Timeline) -> Result<(), PatternError>
+    {
+        match self.timelines.get_mut(at)
+        {
+            Some(slot) => *slot = timeline,
+            None => return Err(PatternError::PatternCountError)
+        }
+        for layer in self.layers.iter_mut()
+        {
+            for cursor in layer.cursors.iter_mut()
+            {
+                if cursor.timeline == Some(at)
+                {
+                    cursor.reader.seek(&timeline, cursor.phase as u64);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_cursor_to_timeline(&mut self, cursor_idx: usize, timeline_idx: usize, restart: bool) -> Result<(), PatternError>
+    {
+        self.set_layer_cursor_to_timeline(0, cursor_idx, timeline_idx, restart)
+    }
+
+    /// Plays a recorded timeline on a cursor from its phase offset
+    pub fn set_layer_cursor_to_timeline(&mut self, layer_idx: usize, cursor_idx: usize, timeline_idx: usize, restart: bool) -> Result<(), PatternError>
+    {
+        let timeline = match self.timelines.get(timeline_idx)
+        {
+            Some(timeline) => *timeline,
+            None => return Err(PatternError::PatternCountError)
+        };
+        if let Some(cursor) = self.layer_mut(layer_idx)?.cursors.get_mut(cursor_idx)
+        {
+            cursor.timeline = Some(timeline_idx);
+            cursor.program = None;
+            cursor.auto_restart = restart;
+            cursor.reader.seek(&timeline, cursor.phase as u64);
+            Ok(())
+        }
+        else
+        {
+            Err(PatternError::InvalidCursorError)
+        }
+    }
+
+    /// Oldest cursor event not yet seen, poll after every run
+    pub fn poll_event(&mut self) -> Option<PatternEvent>
+    {
+        self.pattern_events.pop_front()
+    }
+
+    /// True once after a base layer cursor finishes, whether or not its
+    /// event was polled
+    pub fn take_base_finished(&mut self) -> bool
+    {
+        core::mem::take(&mut self.base_finished)
+    }
+
+    /// Whether any cursor of an enabled layer is still playing
+    pub fn layer_active(&self, layer_idx: usize) -> bool
+    {
+        match self.layers.get(layer_idx)
+        {
+            Some(layer) => layer.enabled && layer.cursors.iter().any(|c| c.enabled),
+            None => false
+        }
+    }
+
+    /// Wakes programs waiting on any of the events in mask during the next run
+    pub fn raise_event(&mut self, mask: u8)
+    {
+        self.events |= mask;
+    }
+
+    /// The error that stopped a cursor's program, if any
+    pub fn cursor_fault(&self, layer_idx: usize, cursor_idx: usize) -> Option<PatternError>
+    {
+        self.layers.get(layer_idx)?.cursors.get(cursor_idx)?.vm.fault
+    }
+
+    /// Offsets a cursor in time from every other cursor sharing its pattern
+    pub fn set_cursor_phase(&mut self, cursor_idx: usize, phase: Microseconds<u32>) -> Result<(), PatternError>
+    {
+        self.set_layer_cursor_phase(0, cursor_idx, phase)
+    }
+
+    pub fn set_layer_cursor_phase(&mut self, layer_idx: usize, cursor_idx: usize, phase: Microseconds<u32>) -> Result<(), PatternError>
+    {
+        let layer = match self.layers.get_mut(layer_idx)
+        {
+            Some(layer) => layer,
+            None => return Err(PatternError::InvalidLayerError)
+        };
+        if let Some(cursor) = layer.cursors.get_mut(cursor_idx)
+        {
+            cursor.phase = phase.integer();
+            // Programs can't be seeked, they always start from the top
+            if let Some(timeline_idx) = cursor.timeline
+            {
+                cursor.reader.seek(&self.timelines[timeline_idx], cursor.phase as u64);
+            }
+            else if cursor.program.is_none()
+            {
+                cursor.seek(&self.patterns[cursor.pattern_index], cursor.phase as u64);
+            }
+            Ok(())
+        }
+        else
+        {
+            Err(PatternError::InvalidCursorError)
+        }
+    }
+
+    /// Points every led at one pattern, spacing their phases evenly over a
+    /// single pass so the pattern chases around the cell
+    pub fn spread_phase(&mut self, pattern_idx: usize, restart: bool) -> Result<(), PatternError>
+    {
+        self.spread_layer_phase(0, pattern_idx, restart)
+    }
+
+    pub fn spread_layer_phase(&mut self, layer_idx: usize, pattern_idx: usize, restart: bool) -> Result<(), PatternError>
+    {
+        let total = match self.patterns.get(pattern_idx)
+        {
+            Some(pattern) => pattern.duration().integer() as u64,
+            None => return Err(PatternError::InvalidPatternError)
+        };
+        for cursor_idx in 0..LED_COUNT
+        {
+            self.set_layer_cursor_to_pattern(layer_idx, cursor_idx, pattern_idx, restart)?;
+            let phase = (total * cursor_idx as u64) / LED_COUNT as u64;
+            self.set_layer_cursor_phase(layer_idx, cursor_idx, Microseconds(phase as u32))?;
+        }
+        Ok(())
+    }
+
+    /// Loops one pattern on every led of a layer, either in unison or with
+    /// phases spread evenly over a pass
+    pub fn play_pattern(&mut self, layer_idx: usize, pattern_idx: usize, spread: bool) -> Result<(), PatternError>
+    {
+        self.play_on_layer(layer_idx, pattern_idx, spread, true)
+    }
+
+    /// Plays one pattern through once on every led of a layer, in unison
+    pub fn play_pattern_once(&mut self, layer_idx: usize, pattern_idx: usize) -> Result<(), PatternError>
+    {
+        self.play_on_layer(layer_idx, pattern_idx, false, false)
+    }
+
+    fn play_on_layer(&mut self, layer_idx: usize, pattern_idx: usize, spread: bool, restart: bool) -> Result<(), PatternError>
+    {
+        if spread
+        {
+            self.spread_layer_phase(layer_idx, pattern_idx, restart)?;
+        }
+        else
+        {
+            for cursor_idx in 0..LED_COUNT
+            {
+                self.set_layer_cursor_to_pattern(layer_idx, cursor_idx, pattern_idx, restart)?;
+                self.set_layer_cursor_phase(layer_idx, cursor_idx, Microseconds(0))?;
+            }
+        }
+        for cursor in self.layer_mut(layer_idx)?.cursors.iter_mut()
+        {
+            cursor.enabled = true;
+        }
+        Ok(())
+    }
+
+    /// Playback settings applied to every cursor on top of its own
+    pub fn playback_mut(&mut self) -> &mut Playback
+    {
+        &mut self.playback
+    }
+
+    pub fn cursor_playback_mut(&mut self, layer_idx: usize, cursor_idx: usize) -> Result<&mut Playback, PatternError>
+    {
+        match self.layer_mut(layer_idx)?.cursors.get_mut(cursor_idx)
+        {
+            Some(cursor) => Ok(&mut cursor.playback),
+            None => Err(PatternError::InvalidCursorError)
+        }
+    }
+
+    pub fn pause(&mut self)
+    {
+        self.playback.pause();
+    }
+
+    pub fn resume(&mut self)
+    {
+        self.playback.resume();
+    }
+
+    /// Moves every pattern and timeline cursor on a shown layer to time past
+    /// its phase, programs can't be seeked and keep running. Finished
+    /// one-shots stay finished.
+    pub fn seek(&mut self, time: Microseconds<u32>)
+    {
+        for layer in self.layers.iter_mut().filter(|layer| layer.enabled)
+        {
+            for cursor in layer.cursors.iter_mut()
+            {
+                let offset = time.integer() as u64 + cursor.phase as u64;
+                let _ = cursor.seek_to(&self.patterns, &self.timelines, offset);
+            }
+        }
+    }
+
+    /// Moves every pattern cursor on a shown layer to the start of an element
+    /// (a frame, for timelines), skipping cursors too short to have it
+    pub fn seek_element(&mut self, element_idx: usize)
+    {
+        for layer in self.layers.iter_mut().filter(|layer| layer.enabled)
+        {
+            for cursor in layer.cursors.iter_mut()
+            {
+                if let Ok(offset) = cursor.element_offset(&self.patterns, &self.timelines, element_idx)
+                {
+                    let _ = cursor.seek_to(&self.patterns, &self.timelines, offset);
+                }
+            }
+        }
+    }
+
+    /// Moves one cursor to time past its phase, restarting it if it had
+    /// finished
+    pub fn seek_cursor(&mut self, layer_idx: usize, cursor_idx: usize, time: Microseconds<u32>) -> Result<(), PatternError>
+    {
+        let layer = match self.layers.get_mut(layer_idx)
+        {
+            Some(layer) => layer,
+            None => return Err(PatternError::InvalidLayerError)
+        };
+        match layer.cursors.get_mut(cursor_idx)
+        {
+            Some(cursor) => {
+                cursor.seek_to(&self.patterns, &self.timelines, time.integer() as u64 + cursor.phase as u64)?;
+                cursor.enabled = true;
+                Ok(())
+            },
+            None => Err(PatternError::InvalidCursorError)
+        }
+    }
+
+    pub fn seek_cursor_to_element(&mut self, layer_idx: usize, cursor_idx: usize, element_idx: usize) -> Result<(), PatternError>
+    {
+        let layer = match self.layers.get_mut(layer_idx)
+        {
+            Some(layer) => layer,
+            None => return Err(PatternError::InvalidLayerError)
+        };
+        match layer.cursors.get_mut(cursor_idx)
+        {
+            Some(cursor) => {
+                let offset = cursor.element_offset(&self.patterns, &self.timelines, element_idx)?;
+                cursor.seek_to(&self.patterns, &self.timelines, offset)?;
+                cursor.enabled = true;
+                Ok(())
+            },
+            None => Err(PatternError::InvalidCursorError)
+        }
+    }
+
+    /// Seeks every looping pattern cursor to where it would be had it started
+    /// at time zero, so cells sharing a clock show the same frame. One-shot
+    /// and program cursors keep their own position, as do cursors under
+    /// live playback control.
+    pub fn align(&mut self, time: Timestamp)
+    {
+        for layer in self.layers.iter_mut()
+        {
+            for cursor in layer.cursors.iter_mut()
+            {
+                if !cursor.auto_restart || !cursor.playback.under(&self.playback).is_normal()
+                {
+                    continue;
+                }
+                let offset = time.micros() + cursor.phase as u64;
+                if let Some(timeline_idx) = cursor.timeline
+                {
+                    cursor.reader.seek(&self.timelines[timeline_idx], offset);
+                }
+                else if cursor.program.is_none()
+                {
+                    cursor.seek(&self.patterns[cursor.pattern_index], offset);
+                }
+            }
+        }
+    }
+}
+
+/// Color of element at elapsed, given the color that preceded it
+fn render(element: &PatternElement, context: &RenderContext, input: Led, elapsed: u32) -> WideLed
+{
+    let color = WideLed::from(element.color);
+    match element.pattern
+    {
+        PatternId::Solid => {
+            // Copy color to output
+            color
+        },
+        PatternId::Blink => {
+            // Copy color or OFF to buffer
+            if elapsed < (element.duration.integer() >> 1)
+            {
+                WIDE_OFF
+            }
+            else
+            {
+                color
+            }
+        },
+        PatternId::Fade => {
+            let factor = transition_factor(element, elapsed);
+            WideLed::from(input).blend_linear(color, factor)
+        },
+        PatternId::Heartbeat => {
+            let level = heartbeat_level(elapsed, element.duration.integer());
+            element.color.scale(level).into()
+        },
+        PatternId::SOS => {
+            if sos_lit(elapsed, element.duration.integer())
+            {
+                color
+            }
+            else
+            {
+                WIDE_OFF
+            }
+        },
+        PatternId::HueRotate | PatternId::HueRotateLong => {
+            let factor = element.easing.apply(phase(elapsed, element.duration.integer()));
+            let long = matches!(element.pattern, PatternId::HueRotateLong);
+            Led::from(Hsv::from(input).blend(Hsv::from(element.color), factor, long)).into()
+        },
+        PatternId::Rainbow => {
+            let factor = element.easing.apply(phase(elapsed, element.duration.integer()));
+            let mut hsv = Hsv::from(element.color);
+            hsv.h = hsv.h.wrapping_add(factor);
+            Led::from(hsv).into()
+        },
+        PatternId::WaveX | PatternId::WaveY | PatternId::Radial | PatternId::Spiral => {
+            let shift = spatial::offset(element.pattern, context.x, context.y, element.param);
+            let t = phase(elapsed, element.duration.integer()).wrapping_sub(shift);
+            element.color.scale(spatial::wave_level(t)).into()
+        },
+        PatternId::Sweep | PatternId::Rotate => {
+            let shift = spatial::led_offset(element.pattern, &context.led, element.param);
+            let t = phase(elapsed, element.duration.integer()).wrapping_sub(shift);
+            element.color.scale(spatial::wave_level(t)).into()
+        },
+        PatternId::EdgeGradient => {
+            let reach = wide(spatial::edge_level(&context.led, element.param % EDGE_COUNT));
+            let factor = ((transition_factor(element, elapsed) as u32 * reach as u32) / WIDE_MAX as u32) as u16;
+            WideLed::from(input).blend_linear(color, factor)
+        },
+        PatternId::Noise => {
+            let level = generators::noise_level(&context.rng, elapsed, element.duration.integer(), element.param);
+            WideLed::from(input).blend_linear(color, wide(level))
+        },
+        PatternId::Fire => {
+            generators::fire(&context.rng, element.color, elapsed, element.duration.integer(), element.param).into()
+        },
+        PatternId::Twinkle => {
+            let level = generators::twinkle_level(&context.rng, elapsed, element.duration.integer(), element.param);
+            WideLed::from(input).blend_linear(color, wide(level))
+        },
+        PatternId::Sparkle => {
+            let level = generators::sparkle_level(&context.rng, elapsed, element.duration.integer(), element.param);
+            WideLed::from(input).blend_linear(color, wide(level))
+        },
+    }
+}
+
+/// An 8 bit fixed point factor at 16 bits
+fn wide(factor: u8) -> u16
+{
+    factor as u16 * 257
+}
+
+/// Position within an element as an 8 bit fixed point on the order of 0..1
+fn phase(elapsed: u32, duration: u32) -> u8
+{
+    if elapsed < duration
+    {
+        (((elapsed as u64) << 8) / duration as u64) as u8
+    }
+    else
+    {
+        0xFF
+    }
+}
+
+/// As phase, in 16 bit fixed point
+fn wide_phase(elapsed: u32, duration: u32) -> u16
+{
+    if elapsed < duration
+    {
+        (((elapsed as u64) << 16) / duration as u64) as u16
+    }
+    else
+    {
+        WIDE_MAX
+    }
+}
+
+/// Eased progress through a transition element, 0..1 in 16 bit fixed point
+fn transition_factor(element: &PatternElement, elapsed: u32) -> u16
+{
+    element.easing.apply_wide(wide_phase(elapsed, element.duration.integer()))
+}
+
+fn heartbeat_level(elapsed: u32, duration: u32) -> u8
+{
+    let t = phase(elapsed, duration);
+    for pair in HEARTBEAT_ENVELOPE.windows(2)
+    {
+        let (start, from) = pair[0];
+        let (end, to) = pair[1];
+        if t < end
+        {
+            let span = (end - start) as i32;
+            let step = (t - start) as i32;
+            return (from as i32 + ((to as i32 - from as i32) * step) / span) as u8;
+        }
+    }
+    0
+}
+
+fn sos_lit(elapsed: u32, duration: u32) -> bool
+{
+    let unit = if elapsed < duration
+    {
+        ((elapsed as u64 * SOS_UNITS as u64) / duration as u64) as u32
+    }
+    else
+    {
+        SOS_UNITS - 1
+    };
+    SOS_SEQUENCE[unit as usize] == b'1'
 }
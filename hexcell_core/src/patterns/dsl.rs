@@ -0,0 +1,342 @@
+//! Text form of a Pattern, for writing shows by hand on the host.
+//!
+//! One element per `;` or line, each a pattern name followed by its settings
+//! in any order, `//` starts a comment:
+//!
+//! ```text
+//! fade #ff0000 1s ease-in; blink #00ff00 500ms x3
+//! wave-x #0000ff 2.5s param=32   // pulse crossing the hive
+//! ```
+//!
+//! | setting        | meaning                                     |
+//! |----------------|---------------------------------------------|
+//! | `#rrggbb`      | color, off when left out                    |
+//! | `1s` `250ms` `40us` | duration, required                     |
+//! | `ease-in` etc. | easing: linear, ease-in, ease-out, ease-in-out, sine, cubic |
+//! | `xN`           | repeat the element N times                  |
+//! | `param=N`      | pattern specific parameter                  |
+
+use std::fmt;
+use std::string::String;
+use embedded_time::duration::*;
+use hexcell_api::display::Led;
+use super::{Pattern, PatternElement, PatternId};
+use super::easing::Easing;
+
+const PATTERN_NAMES: [(&str, PatternId); 19] = [
+    ("solid", PatternId::Solid),
+    ("blink", PatternId::Blink),
+    ("fade", PatternId::Fade),
+    ("heartbeat", PatternId::Heartbeat),
+    ("sos", PatternId::SOS),
+    ("hue", PatternId::HueRotate),
+    ("hue-long", PatternId::HueRotateLong),
+    ("rainbow", PatternId::Rainbow),
+    ("wave-x", PatternId::WaveX),
+    ("wave-y", PatternId::WaveY),
+    ("radial", PatternId::Radial),
+    ("spiral", PatternId::Spiral),
+    ("noise", PatternId::Noise),
+    ("fire", PatternId::Fire),
+    ("twinkle", PatternId::Twinkle),
+    ("sparkle", PatternId::Sparkle),
+    ("sweep", PatternId::Sweep),
+    ("rotate", PatternId::Rotate),
+    ("edge", PatternId::EdgeGradient),
+];
+
+const EASING_NAMES: [(&str, Easing); 6] = [
+    ("linear", Easing::Linear),
+    ("ease-in", Easing::EaseIn),
+    ("ease-out", Easing::EaseOut),
+    ("ease-in-out", Easing::EaseInOut),
+    ("sine", Easing::Sine),
+    ("cubic", Easing::Cubic),
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ParseErrorKind
+{
+    UnknownPattern,
+    UnknownSetting,
+    InvalidColor,
+    InvalidDuration,
+    MissingDuration,
+    InvalidCount,
+    InvalidParam,
+    /// The same setting given twice in one element
+    DuplicateSetting,
+    TooManyElements,
+    EmptyPattern,
+}
+
+/// Where and why text failed to parse, line and column count from 1
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ParseError
+{
+    pub line: usize,
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let message = match self.kind
+        {
+            ParseErrorKind::UnknownPattern => "unknown pattern",
+            ParseErrorKind::UnknownSetting => "unknown setting",
+            ParseErrorKind::InvalidColor => "color must be #rrggbb",
+            ParseErrorKind::InvalidDuration => "duration must be a number followed by s, ms or us",
+            ParseErrorKind::MissingDuration => "element has no duration",
+            ParseErrorKind::InvalidCount => "repeat count must be 1 or more",
+            ParseErrorKind::InvalidParam => "param must be 0 to 255",
+            ParseErrorKind::DuplicateSetting => "setting given twice",
+            ParseErrorKind::TooManyElements => "too many elements",
+            ParseErrorKind::EmptyPattern => "pattern has no elements",
+        };
+        write!(f, "{}:{}: {}", self.line, self.column, message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A word of source text and the column it starts at
+#[derive(Copy, Clone)]
+struct Token<'a>
+{
+    text: &'a str,
+    column: usize,
+}
+
+/// Splits a line into elements (on ';') and each element into words
+fn elements(line: &str) -> std::vec::Vec<std::vec::Vec<Token<'_>>>
+{
+    let code = match line.find("//")
+    {
+        Some(index) => &line[..index],
+        None => line
+    };
+    let mut elements = std::vec::Vec::new();
+    let mut words = std::vec::Vec::new();
+    let mut start: Option<(usize, usize)> = None;
+    let mut column = 0;
+    for (index, c) in code.char_indices()
+    {
+        column += 1;
+        if c.is_whitespace() || c == ';'
+        {
+            if let Some((from, from_column)) = start.take()
+            {
+                words.push(Token { text: &code[from..index], column: from_column });
+            }
+            if c == ';'
+            {
+                elements.push(core::mem::take(&mut words));
+            }
+        }
+        else if start.is_none()
+        {
+            start = Some((index, column));
+        }
+    }
+    if let Some((from, from_column)) = start
+    {
+        words.push(Token { text: &code[from..], column: from_column });
+    }
+    elements.push(words);
+    elements.retain(|words| !words.is_empty());
+    elements
+}
+
+fn parse_color(text: &str) -> Option<Led>
+{
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).ok();
+    Some(Led { r: channel(0)?, g: channel(2)?, b: channel(4)? })
+}
+
+/// Microseconds in a duration such as 1s, 1.5s, 500ms or 40us
+fn parse_duration(text: &str) -> Option<u32>
+{
+    let (number, scale) = if let Some(number) = text.strip_suffix("us")
+    {
+        (number, 1)
+    }
+    else if let Some(number) = text.strip_suffix("ms")
+    {
+        (number, 1_000)
+    }
+    else
+    {
+        (text.strip_suffix('s')?, 1_000_000)
+    };
+    let (whole, fraction) = match number.split_once('.')
+    {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (number, "")
+    };
+    if whole.is_empty() || !whole.chars().all(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let mut micros = whole.parse::<u64>().ok()?.checked_mul(scale)?;
+    // Digits past the microsecond are dropped
+    let mut place = scale;
+    for digit in fraction.chars()
+    {
+        place /= 10;
+        micros += digit.to_digit(10)? as u64 * place;
+    }
+    u32::try_from(micros).ok()
+}
+
+/// Compiles text to a Pattern
+pub fn parse(text: &str) -> Result<Pattern, ParseError>
+{
+    let mut pattern = Pattern::new();
+    let mut last = (1, 1);
+    for (line_index, line) in text.lines().enumerate()
+    {
+        let line_number = line_index + 1;
+        let error = |column: usize, kind: ParseErrorKind| ParseError { line: line_number, column, kind };
+        for words in elements(line)
+        {
+            let name = words[0];
+            let pattern_id = match PATTERN_NAMES.iter().find(|(text, _)| *text == name.text)
+            {
+                Some((_, id)) => *id,
+                None => return Err(error(name.column, ParseErrorKind::UnknownPattern))
+            };
+            let mut element = PatternElement { pattern: pattern_id, ..Default::default() };
+            let (mut color, mut duration, mut easing, mut count, mut param) = (false, false, false, None, false);
+            for word in words[1..].iter()
+            {
+                let duplicate = if word.text.starts_with('#')
+                {
+                    element.color = parse_color(word.text).ok_or(error(word.column, ParseErrorKind::InvalidColor))?;
+                    core::mem::replace(&mut color, true)
+                }
+                else if word.text.starts_with(|c: char| c.is_ascii_digit())
+                {
+                    element.duration = Microseconds(parse_duration(word.text).ok_or(error(word.column, ParseErrorKind::InvalidDuration))?);
+                    core::mem::replace(&mut duration, true)
+                }
+                else if let Some(value) = word.text.strip_prefix("param=")
+                {
+                    element.param = value.parse::<u8>().map_err(|_| error(word.column, ParseErrorKind::InvalidParam))?;
+                    core::mem::replace(&mut param, true)
+                }
+                else if let Some((_, curve)) = EASING_NAMES.iter().find(|(text, _)| *text == word.text)
+                {
+                    element.easing = *curve;
+                    core::mem::replace(&mut easing, true)
+                }
+                else if let Some(value) = word.text.strip_prefix('x')
+                {
+                    match value.parse::<usize>()
+                    {
+                        Ok(n) if n > 0 => count.replace(n).is_some(),
+                        _ => return Err(error(word.column, ParseErrorKind::InvalidCount))
+                    }
+                }
+                else
+                {
+                    return Err(error(word.column, ParseErrorKind::UnknownSetting));
+                };
+                if duplicate
+                {
+                    return Err(error(word.column, ParseErrorKind::DuplicateSetting));
+                }
+            }
+            if !duration
+            {
+                return Err(error(name.column, ParseErrorKind::MissingDuration));
+            }
+            for _ in 0..count.unwrap_or(1)
+            {
+                if pattern.next(element).is_err()
+                {
+                    return Err(error(name.column, ParseErrorKind::TooManyElements));
+                }
+            }
+        }
+        last = (line_number, line.chars().count() + 1);
+    }
+    if pattern.data.is_empty()
+    {
+        return Err(ParseError { line: last.0, column: last.1, kind: ParseErrorKind::EmptyPattern });
+    }
+    Ok(pattern)
+}
+
+fn same_element(a: &PatternElement, b: &PatternElement) -> bool
+{
+    a.pattern as u8 == b.pattern as u8 && a.color == b.color && a.duration.integer() == b.duration.integer()
+        && a.easing == b.easing && a.param == b.param
+}
+
+impl fmt::Display for PatternElement
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let name = PATTERN_NAMES.iter().find(|(_, id)| *id as u8 == self.pattern as u8).map_or("solid", |(text, _)| *text);
+        write!(f, "{} #{:02x}{:02x}{:02x} ", name, self.color.r, self.color.g, self.color.b)?;
+        let micros = self.duration.integer();
+        if micros != 0 && micros % 1_000_000 == 0
+        {
+            write!(f, "{}s", micros / 1_000_000)?;
+        }
+        else if micros != 0 && micros % 1_000 == 0
+        {
+            write!(f, "{}ms", micros / 1_000)?;
+        }
+        else
+        {
+            write!(f, "{}us", micros)?;
+        }
+        if self.easing != Easing::Linear
+        {
+            let easing = EASING_NAMES.iter().find(|(_, curve)| *curve == self.easing).map_or("linear", |(text, _)| *text);
+            write!(f, " {}", easing)?;
+        }
+        if self.param != 0
+        {
+            write!(f, " param={}", self.param)?;
+        }
+        Ok(())
+    }
+}
+
+/// One element per line, runs of identical elements folded into xN
+impl fmt::Display for Pattern
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let mut index = 0;
+        while index < self.data.len()
+        {
+            let element = &self.data[index];
+            let run = self.data[index..].iter().take_while(|other| same_element(element, other)).count();
+            write!(f, "{}", element)?;
+            if run > 1
+            {
+                write!(f, " x{}", run)?;
+            }
+            writeln!(f, ";")?;
+            index += run;
+        }
+        Ok(())
+    }
+}
+
+/// Pretty-prints pattern in the form parse reads
+pub fn to_text(pattern: &Pattern) -> String
+{
+    std::format!("{}", pattern)
+}
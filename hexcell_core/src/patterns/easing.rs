@@ -0,0 +1,104 @@
+/// Number of segments in each easing table, entries are spaced 8/256ths apart
+const EASING_SEGMENTS: usize = 32;
+type EasingTable = [u8; EASING_SEGMENTS + 1];
+
+// Curves sampled at t = n/32 and scaled to 0..255
+const EASE_IN: EasingTable = [
+    0, 0, 1, 2, 4, 6, 9, 12, 16, 20, 25, 30, 36, 42, 49, 56,
+    64, 72, 81, 90, 100, 110, 121, 132, 143, 156, 168, 182, 195, 209, 224, 239,
+    255,
+];
+const EASE_OUT: EasingTable = [
+    0, 16, 31, 46, 60, 73, 87, 99, 112, 123, 134, 145, 155, 165, 174, 183,
+    191, 199, 206, 213, 219, 225, 230, 235, 239, 243, 246, 249, 251, 253, 254, 255,
+    255,
+];
+const EASE_IN_OUT: EasingTable = [
+    0, 0, 2, 4, 8, 12, 18, 24, 32, 40, 50, 60, 72, 84, 98, 112,
+    128, 143, 157, 171, 183, 195, 205, 215, 223, 231, 237, 243, 247, 251, 253, 255,
+    255,
+];
+const SINE: EasingTable = [
+    0, 1, 2, 5, 10, 15, 21, 29, 37, 47, 57, 67, 79, 90, 103, 115,
+    127, 140, 152, 165, 176, 188, 198, 208, 218, 226, 234, 240, 245, 250, 253, 254,
+    255,
+];
+const CUBIC: EasingTable = [
+    0, 0, 0, 1, 2, 4, 7, 11, 16, 23, 31, 41, 54, 68, 85, 105,
+    128, 150, 170, 187, 201, 214, 224, 232, 239, 244, 248, 251, 253, 254, 255, 255,
+    255,
+];
+
+/// Shape of a transition over the life of an element
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Easing
+{
+    #[default]
+    Linear,
+    /// Quadratic, slow start
+    EaseIn,
+    /// Quadratic, slow finish
+    EaseOut,
+    /// Quadratic, slow start and finish
+    EaseInOut,
+    /// Half cosine, slow start and finish
+    Sine,
+    /// Cubic, slow start and finish
+    Cubic,
+}
+
+impl Easing
+{
+    fn table(self) -> Option<&'static EasingTable>
+    {
+        match self
+        {
+            Easing::Linear => None,
+            Easing::EaseIn => Some(&EASE_IN),
+            Easing::EaseOut => Some(&EASE_OUT),
+            Easing::EaseInOut => Some(&EASE_IN_OUT),
+            Easing::Sine => Some(&SINE),
+            Easing::Cubic => Some(&CUBIC),
+        }
+    }
+
+    /// Maps a linear 8 bit fixed point progress (0..1) onto this curve
+    pub fn apply(self, t: u8) -> u8
+    {
+        let table = match self.table()
+        {
+            Some(table) => table,
+            None => return t
+        };
+        // Transitions must land exactly on their target
+        if t == 0xFF
+        {
+            return 0xFF;
+        }
+        let index = (t >> 3) as usize;
+        let fraction = (t & 0x07) as i16;
+        let from = table[index] as i16;
+        let to = table[index + 1] as i16;
+        (from + (((to - from) * fraction) >> 3)) as u8
+    }
+
+    /// As apply, in 16 bit fixed point
+    pub fn apply_wide(self, t: u16) -> u16
+    {
+        let table = match self.table()
+        {
+            Some(table) => table,
+            None => return t
+        };
+        if t == 0xFFFF
+        {
+            return 0xFFFF;
+        }
+        let index = (t >> 11) as usize;
+        let fraction = (t & 0x07FF) as i32;
+        let from = table[index] as i32 * 257;
+        let to = table[index + 1] as i32 * 257;
+        (from + (((to - from) * fraction) >> 11)) as u16
+    }
+}
@@ -0,0 +1,185 @@
+use hexcell_api::display::Led;
+use hexcell_api::messaging::{MessageBuffer, MESSAGE_SIZE};
+use embedded_time::duration::*;
+use zerocopy::{AsBytes, FromBytes, FromZeroes, Unaligned};
+use zerocopy::byteorder::{LittleEndian, U32};
+use crate::hexcore_errors::PatternError;
+use super::{Pattern, PatternElement, PatternId, MAX_PATTERN_ELEMENTS};
+use super::easing::Easing;
+
+/// Bump whenever the wire layout below changes
+pub const PATTERN_FORMAT_VERSION: u8 = 2;
+
+/// Leads every encoded pattern
+#[repr(C)]
+#[derive(Copy, Clone, AsBytes, FromBytes, FromZeroes, Unaligned)]
+struct PatternHeader
+{
+    version: u8,
+    count: u8,
+}
+
+/// Little endian, byte aligned image of a PatternElement
+#[repr(C)]
+#[derive(Copy, Clone, AsBytes, FromBytes, FromZeroes, Unaligned)]
+struct PatternElementWire
+{
+    pattern: u8,
+    r: u8,
+    g: u8,
+    b: u8,
+    duration: U32<LittleEndian>,
+    easing: u8,
+    param: u8,
+}
+
+pub const PATTERN_HEADER_SIZE: usize = core::mem::size_of::<PatternHeader>();
+pub const PATTERN_ELEMENT_SIZE: usize = core::mem::size_of::<PatternElementWire>();
+/// Largest possible encoded pattern
+pub const ENCODED_PATTERN_MAX_SIZE: usize = PATTERN_HEADER_SIZE + (PATTERN_ELEMENT_SIZE * MAX_PATTERN_ELEMENTS);
+
+// A full pattern must ship in a single message
+const _: () = assert!(ENCODED_PATTERN_MAX_SIZE <= MESSAGE_SIZE);
+
+impl TryFrom<u8> for PatternId
+{
+    type Error = PatternError;
+    fn try_from(value: u8) -> Result<Self, Self::Error>
+    {
+        match value
+        {
+            0 => Ok(PatternId::Solid),
+            1 => Ok(PatternId::Blink),
+            2 => Ok(PatternId::Fade),
+            3 => Ok(PatternId::Heartbeat),
+            4 => Ok(PatternId::SOS),
+            5 => Ok(PatternId::HueRotate),
+            6 => Ok(PatternId::HueRotateLong),
+            7 => Ok(PatternId::Rainbow),
+            8 => Ok(PatternId::WaveX),
+            9 => Ok(PatternId::WaveY),
+            10 => Ok(PatternId::Radial),
+            11 => Ok(PatternId::Spiral),
+            12 => Ok(PatternId::Noise),
+            13 => Ok(PatternId::Fire),
+            14 => Ok(PatternId::Twinkle),
+            15 => Ok(PatternId::Sparkle),
+            16 => Ok(PatternId::Sweep),
+            17 => Ok(PatternId::Rotate),
+            18 => Ok(PatternId::EdgeGradient),
+            _ => Err(PatternError::UnknownPatternIdError),
+        }
+    }
+}
+
+impl TryFrom<u8> for Easing
+{
+    type Error = PatternError;
+    fn try_from(value: u8) -> Result<Self, Self::Error>
+    {
+        match value
+        {
+            0 => Ok(Easing::Linear),
+            1 => Ok(Easing::EaseIn),
+            2 => Ok(Easing::EaseOut),
+            3 => Ok(Easing::EaseInOut),
+            4 => Ok(Easing::Sine),
+            5 => Ok(Easing::Cubic),
+            _ => Err(PatternError::UnknownEasingError),
+        }
+    }
+}
+
+fn push_bytes(buffer: &mut MessageBuffer, bytes: &[u8]) -> Result<(), PatternError>
+{
+    match buffer.extend_from_slice(bytes)
+    {
+        Ok(()) => Ok(()),
+        Err(_) => Err(PatternError::PatternSizeError)
+    }
+}
+
+impl PatternElement
+{
+    /// Appends the wire form of this element to buffer
+    pub fn encode(&self, buffer: &mut MessageBuffer) -> Result<(), PatternError>
+    {
+        let wire = PatternElementWire {
+            pattern: self.pattern as u8,
+            r: self.color.r,
+            g: self.color.g,
+            b: self.color.b,
+            duration: U32::new(self.duration.integer()),
+            easing: self.easing as u8,
+            param: self.param,
+        };
+        push_bytes(buffer, wire.as_bytes())
+    }
+
+    /// Reads a single element from the front of bytes
+    pub fn decode(bytes: &[u8]) -> Result<PatternElement, PatternError>
+    {
+        match PatternElementWire::read_from_prefix(bytes)
+        {
+            Some(wire) => Ok(PatternElement {
+                pattern: PatternId::try_from(wire.pattern)?,
+                color: Led { r: wire.r, g: wire.g, b: wire.b },
+                duration: Microseconds(wire.duration.get()),
+                easing: Easing::try_from(wire.easing)?,
+                param: wire.param,
+            }),
+            None => Err(PatternError::TruncatedDataError)
+        }
+    }
+}
+
+impl Pattern
+{
+    /// Writes a versioned encoding of this pattern into buffer, replacing its contents
+    pub fn encode(&self, buffer: &mut MessageBuffer) -> Result<(), PatternError>
+    {
+        buffer.clear();
+        let header = PatternHeader { version: PATTERN_FORMAT_VERSION, count: self.data.len() as u8 };
+        push_bytes(buffer, header.as_bytes())?;
+        for element in self.data.iter()
+        {
+            element.encode(buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds a pattern from its encoding, rejecting truncated, oversized or unknown data
+    pub fn decode(bytes: &[u8]) -> Result<Pattern, PatternError>
+    {
+        let header = match PatternHeader::read_from_prefix(bytes)
+        {
+            Some(header) => header,
+            None => return Err(PatternError::TruncatedDataError)
+        };
+        if header.version != PATTERN_FORMAT_VERSION
+        {
+            return Err(PatternError::UnsupportedVersionError);
+        }
+        let count = header.count as usize;
+        if count > MAX_PATTERN_ELEMENTS
+        {
+            return Err(PatternError::PatternSizeError);
+        }
+        let expected = PATTERN_HEADER_SIZE + (count * PATTERN_ELEMENT_SIZE);
+        if bytes.len() < expected
+        {
+            return Err(PatternError::TruncatedDataError);
+        }
+        if bytes.len() > expected
+        {
+            return Err(PatternError::InvalidPatternError);
+        }
+
+        let mut pattern = Pattern::new();
+        for chunk in bytes[PATTERN_HEADER_SIZE..].chunks_exact(PATTERN_ELEMENT_SIZE)
+        {
+            pattern.next(PatternElement::decode(chunk)?)?;
+        }
+        Ok(pattern)
+    }
+}
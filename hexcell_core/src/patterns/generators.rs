@@ -0,0 +1,119 @@
+// Procedural elements: noise, fire, twinkle and sparkle. Every value comes
+// from a counter based generator, so a cell always draws the same frames for
+// the same seed and time no matter how often it happens to be ticked.
+use hexcell_api::display::Led;
+use super::easing::Easing;
+
+/// Control points per element when param is left at zero
+const DEFAULT_NOISE_POINTS: u8 = 8;
+const DEFAULT_TWINKLE_SLOTS: u8 = 4;
+const DEFAULT_SPARKLE_SLOTS: u8 = 32;
+/// How far fire dims at its coolest, out of 255
+const FIRE_DEPTH: u32 = 176;
+/// One sparkle slot in this many flashes
+const SPARKLE_CHANCE: u32 = 8;
+
+/// A deterministic random source. Values are looked up by position rather
+/// than drawn in sequence, so any frame can be rendered without replaying
+/// the ones before it.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Rng
+{
+    seed: u32,
+}
+
+impl Rng
+{
+    pub const fn new(seed: u32) -> Rng
+    {
+        Rng { seed }
+    }
+
+    /// Random bits for position
+    pub fn at(&self, position: u32) -> u32
+    {
+        mix(self.seed ^ mix(position))
+    }
+
+    /// An independent generator, e.g. one per led
+    pub fn stream(&self, index: u32) -> Rng
+    {
+        Rng { seed: self.at(index ^ 0x9E37_79B9) }
+    }
+}
+
+/// Integer hash with good avalanche (lowbias32)
+fn mix(mut x: u32) -> u32
+{
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^= x >> 16;
+    x
+}
+
+/// Which of slots equal parts of the element elapsed falls in, and how far
+/// through that part it is (0..255)
+fn slot(elapsed: u32, duration: u32, slots: u8) -> (u32, u8)
+{
+    if duration == 0
+    {
+        return (0, 0);
+    }
+    let t = ((elapsed.min(duration) as u64 * slots as u64) << 8) / duration as u64;
+    ((t >> 8) as u32, t as u8)
+}
+
+fn or_default(param: u8, default: u8) -> u8
+{
+    if param == 0 { default } else { param }
+}
+
+/// Smoothly wandering level, param control points per element
+pub fn noise_level(rng: &Rng, elapsed: u32, duration: u32, param: u8) -> u8
+{
+    let (index, t) = slot(elapsed, duration, or_default(param, DEFAULT_NOISE_POINTS));
+    let from = rng.at(index) as u8 as i32;
+    let to = rng.at(index + 1) as u8 as i32;
+    let t = Easing::EaseInOut.apply(t) as i32;
+    (from + ((to - from) * t) / 255) as u8
+}
+
+/// Flickering flame in color, reddening as it dims. Two octaves of noise,
+/// param sets the slower one's rate.
+pub fn fire(rng: &Rng, color: Led, elapsed: u32, duration: u32, param: u8) -> Led
+{
+    let points = or_default(param, DEFAULT_NOISE_POINTS);
+    let slow = noise_level(rng, elapsed, duration, points) as u32;
+    let fast = noise_level(&rng.stream(1), elapsed, duration, points.saturating_mul(4)) as u32;
+    let heat = (slow * 3 + fast) / 4;
+    let level = 255 - (FIRE_DEPTH * (255 - heat)) / 255;
+    let scale = |channel: u8, times: u32| (0..times).fold(channel as u32, |c, _| (c * level) / 255) as u8;
+    Led { r: scale(color.r, 1), g: scale(color.g, 2), b: scale(color.b, 3) }
+}
+
+/// Soft swells of varying brightness in about half of param slots
+pub fn twinkle_level(rng: &Rng, elapsed: u32, duration: u32, param: u8) -> u8
+{
+    let (index, t) = slot(elapsed, duration, or_default(param, DEFAULT_TWINKLE_SLOTS));
+    let bits = rng.at(index);
+    if bits & 1 == 0
+    {
+        return 0;
+    }
+    let peak = 128 + ((bits >> 8) & 0x7F);
+    let swell = if t < 128 { t as u32 * 2 } else { (255 - t as u32) * 2 };
+    ((swell * peak) / 255) as u8
+}
+
+/// Rare sharp flashes that decay over their slot, param slots per element
+pub fn sparkle_level(rng: &Rng, elapsed: u32, duration: u32, param: u8) -> u8
+{
+    let (index, t) = slot(elapsed, duration, or_default(param, DEFAULT_SPARKLE_SLOTS));
+    if rng.at(index) % SPARKLE_CHANCE != 0
+    {
+        return 0;
+    }
+    255 - t
+}
@@ -0,0 +1,112 @@
+// Live transport controls, applied per cursor and again for the whole engine
+
+/// Speed multiplier in 8.8 fixed point
+pub type Speed = u16;
+/// Plays in real time
+pub const NORMAL_SPEED: Speed = 0x0100;
+
+/// How a cursor (or, for the engine's copy, every cursor) moves through time
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Playback
+{
+    /// Holds the current frame, seeking still moves it
+    pub paused: bool,
+    pub speed: Speed,
+    /// Plays from the end towards the start
+    pub reverse: bool,
+    /// Turns around at either end instead of wrapping. A one-shot cursor
+    /// stops after going there and back once.
+    pub ping_pong: bool,
+}
+
+impl Default for Playback
+{
+    fn default() -> Playback
+    {
+        Playback { paused: false, speed: NORMAL_SPEED, reverse: false, ping_pong: false }
+    }
+}
+
+impl Playback
+{
+    pub fn pause(&mut self)
+    {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self)
+    {
+        self.paused = false;
+    }
+
+    /// Whether time passes exactly as the clock does
+    pub fn is_normal(&self) -> bool
+    {
+        *self == Playback::default()
+    }
+
+    /// Combines a cursor's settings with the engine's
+    pub(crate) fn under(&self, global: &Playback) -> Playback
+    {
+        let speed = (self.speed as u32 * global.speed as u32) >> 8;
+        Playback {
+            paused: self.paused || global.paused,
+            speed: speed.min(Speed::MAX as u32) as Speed,
+            reverse: self.reverse != global.reverse,
+            ping_pong: self.ping_pong || global.ping_pong,
+        }
+    }
+}
+
+/// Scales real time to play time, keeping the fraction of a microsecond a
+/// slow speed would otherwise lose every tick
+pub(crate) fn scale(delta: u32, speed: Speed, remainder: &mut u8) -> u32
+{
+    let total = ((delta as u64) * speed as u64) + *remainder as u64;
+    *remainder = total as u8;
+    (total >> 8).min(u32::MAX as u64) as u32
+}
+
+/// Where a cursor lands after moving delta along a pass of length total.
+/// Returns the new position and whether it is now heading backwards, or
+/// None once a one-shot cursor has run off the end.
+pub(crate) fn travel(position: u64, total: u64, delta: u64, backwards: bool, ping_pong: bool, looping: bool) -> Option<(u64, bool)>
+{
+    if total == 0
+    {
+        return Some((0, backwards));
+    }
+    let last = total - 1;
+    if ping_pong
+    {
+        // Unfold the bounce into one straight run over twice the length,
+        // the way back mirrors the way there
+        let unfolded = if backwards { 2 * total - 1 - position.min(last) } else { position };
+        let moved = unfolded + delta;
+        if !looping && moved >= 2 * total
+        {
+            return None;
+        }
+        let moved = moved % (2 * total);
+        return if moved < total { Some((moved, false)) } else { Some((2 * total - 1 - moved, true)) };
+    }
+    if backwards
+    {
+        if delta <= position
+        {
+            return Some((position - delta, true));
+        }
+        if !looping
+        {
+            return None;
+        }
+        let under = (delta - position) % total;
+        return Some(((total - under) % total, true));
+    }
+    let moved = position + delta;
+    if !looping && moved >= total
+    {
+        return None;
+    }
+    Some((moved % total, false))
+}
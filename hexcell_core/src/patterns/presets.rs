@@ -0,0 +1,343 @@
+use hexcell_api::display::{Hsv, Led, LED_COUNT};
+use embedded_time::duration::*;
+use heapless::Vec;
+use zerocopy::{AsBytes, FromBytes, FromZeroes, Unaligned};
+use zerocopy::byteorder::{LittleEndian, U32};
+use crate::hexcore_errors::PatternError;
+use super::{Pattern, PatternBuilder, PatternElement, PatternId, OFF};
+use super::easing::Easing;
+
+/// Small numeric handle for a preset, built-ins count up from zero
+pub type PresetId = u8;
+/// Presets stored at runtime take ids from here up
+pub const USER_PRESET_BASE: PresetId = 0x80;
+/// Each user preset holds a full Pattern, reduce if RAM is tight
+pub const MAX_USER_PRESETS: usize = 4;
+
+const WHITE: Led = Led { r: 255, g: 255, b: 255 };
+
+/// Settings every preset understands
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PresetParams
+{
+    /// Main color of a built-in, or a tint multiplied into a stored preset
+    pub color: Led,
+    /// Length of one pass, zero keeps the preset's own timing
+    pub period: Microseconds<u32>,
+}
+
+impl Default for PresetParams
+{
+    fn default() -> PresetParams
+    {
+        PresetParams { color: WHITE, period: Microseconds(0) }
+    }
+}
+
+/// Presets compiled into every cell
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum BuiltinPreset
+{
+    Off,
+    Solid,
+    Blink,
+    /// Slow fade in and out
+    Breathe,
+    Heartbeat,
+    SOS,
+    /// Fully saturated hue cycle starting from the color's hue
+    Rainbow,
+    /// A short flash running around the leds of each cell
+    Chase,
+    /// Pulses sweeping across the hive along x
+    Wave,
+    /// Rings spreading out from the root cell
+    Ripple,
+}
+
+pub const BUILTIN_PRESET_COUNT: PresetId = 10;
+
+impl TryFrom<u8> for BuiltinPreset
+{
+    type Error = PatternError;
+    fn try_from(value: u8) -> Result<Self, Self::Error>
+    {
+        match value
+        {
+            0 => Ok(BuiltinPreset::Off),
+            1 => Ok(BuiltinPreset::Solid),
+            2 => Ok(BuiltinPreset::Blink),
+            3 => Ok(BuiltinPreset::Breathe),
+            4 => Ok(BuiltinPreset::Heartbeat),
+            5 => Ok(BuiltinPreset::SOS),
+            6 => Ok(BuiltinPreset::Rainbow),
+            7 => Ok(BuiltinPreset::Chase),
+            8 => Ok(BuiltinPreset::Wave),
+            9 => Ok(BuiltinPreset::Ripple),
+            _ => Err(PatternError::UnknownPresetError),
+        }
+    }
+}
+
+impl BuiltinPreset
+{
+    /// Length of one pass when the params leave it unset
+    fn default_period(&self) -> u32
+    {
+        match self
+        {
+            BuiltinPreset::Off | BuiltinPreset::Solid => 1_000_000,
+            BuiltinPreset::Blink => 1_000_000,
+            BuiltinPreset::Breathe => 4_000_000,
+            BuiltinPreset::Heartbeat => 1_000_000,
+            BuiltinPreset::SOS => 6_800_000,
+            BuiltinPreset::Rainbow => 10_000_000,
+            BuiltinPreset::Chase => 900_000,
+            BuiltinPreset::Wave | BuiltinPreset::Ripple => 3_000_000,
+        }
+    }
+
+    /// Whether the leds of a cell are spread over a pass rather than in unison
+    fn spread(&self) -> bool
+    {
+        matches!(self, BuiltinPreset::Chase)
+    }
+
+    fn pattern(&self, params: &PresetParams) -> Pattern
+    {
+        let period = match params.period.integer()
+        {
+            0 => self.default_period(),
+            period => period
+        };
+        let element = PatternElement { color: params.color, duration: Microseconds(period), ..Default::default() };
+        match self
+        {
+            BuiltinPreset::Off => PatternBuilder::new()
+                .then(PatternElement { color: OFF, ..element })
+                .finish(),
+            BuiltinPreset::Solid => PatternBuilder::new().then(element).finish(),
+            BuiltinPreset::Blink => PatternBuilder::new()
+                .then(PatternElement { pattern: PatternId::Blink, ..element })
+                .finish(),
+            BuiltinPreset::Breathe => {
+                let half = PatternElement { pattern: PatternId::Fade, duration: Microseconds(period / 2), easing: Easing::Sine, ..element };
+                PatternBuilder::new()
+                .then(half)
+                .then(PatternElement { color: OFF, ..half })
+                .finish()
+            },
+            BuiltinPreset::Heartbeat => PatternBuilder::new()
+                .then(PatternElement { pattern: PatternId::Heartbeat, ..element })
+                .finish(),
+            BuiltinPreset::SOS => PatternBuilder::new()
+                .then(PatternElement { pattern: PatternId::SOS, ..element })
+                .finish(),
+            BuiltinPreset::Rainbow => {
+                let mut hsv = Hsv::from(params.color);
+                hsv.s = 255;
+                PatternBuilder::new()
+                .then(PatternElement { pattern: PatternId::Rainbow, color: hsv.into(), ..element })
+                .finish()
+            },
+            BuiltinPreset::Chase => {
+                let flash = period / LED_COUNT as u32;
+                PatternBuilder::new()
+                .then(PatternElement { duration: Microseconds(flash), ..element })
+                .then(PatternElement { color: OFF, duration: Microseconds(period - flash), ..element })
+                .finish()
+            },
+            BuiltinPreset::Wave => PatternBuilder::new()
+                .then(PatternElement { pattern: PatternId::WaveX, param: 32, ..element })
+                .finish(),
+            BuiltinPreset::Ripple => PatternBuilder::new()
+                .then(PatternElement { pattern: PatternId::Radial, param: 32, ..element })
+                .finish(),
+        }
+    }
+}
+
+/// A preset ready to load into the engine
+pub struct Preset
+{
+    pub pattern: Pattern,
+    /// Spread the leds' phases evenly rather than running them in unison
+    pub spread: bool,
+}
+
+/// A pattern stored at runtime, e.g. shipped over the network
+#[derive(Clone)]
+struct UserPreset
+{
+    id: PresetId,
+    pattern: Pattern,
+    spread: bool,
+}
+
+/// Built-in presets plus a few stored at runtime, looked up by id
+#[derive(Default)]
+pub struct PresetRegistry
+{
+    user: Vec<UserPreset, MAX_USER_PRESETS>,
+}
+
+impl PresetRegistry
+{
+    pub fn new() -> PresetRegistry
+    {
+        PresetRegistry { user: Vec::new() }
+    }
+
+    /// Stores (or replaces) a user preset, id must be USER_PRESET_BASE or above
+    pub fn store(&mut self, id: PresetId, pattern: Pattern, spread: bool) -> Result<(), PatternError>
+    {
+        if id < USER_PRESET_BASE
+        {
+            return Err(PatternError::UnknownPresetError);
+        }
+        if pattern.data.is_empty()
+        {
+            return Err(PatternError::InvalidPatternError);
+        }
+        let preset = UserPreset { id, pattern, spread };
+        match self.user.iter_mut().find(|p| p.id == id)
+        {
+            Some(slot) => *slot = preset,
+            None => {
+                if self.user.push(preset).is_err()
+                {
+                    return Err(PatternError::PatternCountError);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: PresetId) -> Result<(), PatternError>
+    {
+        match self.user.iter().position(|p| p.id == id)
+        {
+            Some(index) => {
+                self.user.remove(index);
+                Ok(())
+            },
+            None => Err(PatternError::UnknownPresetError)
+        }
+    }
+
+    pub fn contains(&self, id: PresetId) -> bool
+    {
+        BuiltinPreset::try_from(id).is_ok() || self.user.iter().any(|p| p.id == id)
+    }
+
+    /// Builds preset id with params applied
+    pub fn build(&self, id: PresetId, params: &PresetParams) -> Result<Preset, PatternError>
+    {
+        if let Ok(builtin) = BuiltinPreset::try_from(id)
+        {
+            return Ok(Preset { pattern: builtin.pattern(params), spread: builtin.spread() });
+        }
+        let stored = match self.user.iter().find(|p| p.id == id)
+        {
+            Some(stored) => stored,
+            None => return Err(PatternError::UnknownPresetError)
+        };
+        let mut pattern = stored.pattern.clone();
+        // Stretch every element so one pass lasts period
+        let total = pattern.duration().integer() as u64;
+        let period = params.period.integer() as u64;
+        for element in pattern.data.iter_mut()
+        {
+            if period != 0 && total != 0
+            {
+                element.duration = Microseconds(((element.duration.integer() as u64 * period) / total) as u32);
+            }
+            element.color = element.color.modulate(params.color);
+        }
+        Ok(Preset { pattern, spread: stored.spread })
+    }
+}
+
+/// Whether a preset command stops at the cell that receives it
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum PresetScope
+{
+    Cell,
+    /// Passed on to every neighbor until the whole hive has it
+    Hive,
+}
+
+/// Switches a cell (or the hive) to a preset, little endian and byte aligned
+#[repr(C)]
+#[derive(Copy, Clone, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct PresetCommand
+{
+    preset: u8,
+    scope: u8,
+    // Hive commands are flooded, cells drop ones they have already applied
+    sequence: u8,
+    r: u8,
+    g: u8,
+    b: u8,
+    period: U32<LittleEndian>,
+}
+
+pub const PRESET_COMMAND_SIZE: usize = core::mem::size_of::<PresetCommand>();
+
+impl PresetCommand
+{
+    pub fn new(id: PresetId, params: &PresetParams, scope: PresetScope, sequence: u8) -> PresetCommand
+    {
+        PresetCommand {
+            preset: id,
+            scope: scope as u8,
+            sequence,
+            r: params.color.r,
+            g: params.color.g,
+            b: params.color.b,
+            period: U32::new(params.period.integer()),
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<PresetCommand, PatternError>
+    {
+        if bytes.len() != PRESET_COMMAND_SIZE
+        {
+            return Err(PatternError::TruncatedDataError);
+        }
+        let command = match PresetCommand::read_from(bytes)
+        {
+            Some(command) => command,
+            None => return Err(PatternError::TruncatedDataError)
+        };
+        command.scope()?;
+        Ok(command)
+    }
+
+    pub fn id(&self) -> PresetId
+    {
+        self.preset
+    }
+
+    pub fn params(&self) -> PresetParams
+    {
+        PresetParams { color: Led { r: self.r, g: self.g, b: self.b }, period: Microseconds(self.period.get()) }
+    }
+
+    pub fn scope(&self) -> Result<PresetScope, PatternError>
+    {
+        match self.scope
+        {
+            0 => Ok(PresetScope::Cell),
+            1 => Ok(PresetScope::Hive),
+            _ => Err(PatternError::InvalidPatternError)
+        }
+    }
+
+    pub fn sequence(&self) -> u8
+    {
+        self.sequence
+    }
+}
@@ -0,0 +1,91 @@
+// Hive-wide patterns: every cell evaluates the same formula for its own
+// position, so the installation animates as one without streaming frames.
+// Intra-cell patterns do the same per led, using its place in the cell.
+use hexcell_api::display::{cosine, sine, LedPosition, EDGE_COUNT, MAX_RADIUS};
+use super::PatternId;
+use super::easing::Easing;
+
+const DEFAULT_SWEEP_SHIFT: u8 = 128;
+const DEFAULT_ROTATE_ARMS: u8 = 1;
+
+/// Hex distance from the root cell. Positions are the offset coordinates
+/// NetworkId::compute_external_id hands out: columns are x, and even columns
+/// meet their neighbours on rows y and y + 1, odd columns on y - 1 and y.
+pub fn hex_distance(x: i16, y: i16) -> u16
+{
+    // Convert to axial, where every column shares the same neighbour offsets
+    let q = x as i32;
+    let r = y as i32 - (q + 1).div_euclid(2);
+    ((q.abs() + r.abs() + (q + r).abs()) / 2) as u16
+}
+
+/// Approximate angle of a position around the root in 256ths of a turn,
+/// counter clockwise from the +x axis
+pub fn angle(x: i16, y: i16) -> u8
+{
+    if x == 0 && y == 0
+    {
+        return 0;
+    }
+    let (ax, ay) = ((x as i32).abs(), (y as i32).abs());
+    // Within the first quadrant, 64 is a quarter turn
+    let a = if ax >= ay { (ay * 32) / ax } else { 64 - ((ax * 32) / ay) };
+    let a = match (x >= 0, y >= 0)
+    {
+        (true, true) => a,
+        (false, true) => 128 - a,
+        (false, false) => 128 + a,
+        (true, false) => 256 - a,
+    };
+    a as u8
+}
+
+/// Phase shift (in 256ths of a period) of a spatial element at a position,
+/// step is the shift per cell
+pub fn offset(pattern: PatternId, x: i16, y: i16, step: u8) -> u8
+{
+    let step = step as i32;
+    let shift = match pattern
+    {
+        PatternId::WaveX => x as i32 * step,
+        PatternId::WaveY => y as i32 * step,
+        PatternId::Radial => hex_distance(x, y) as i32 * step,
+        PatternId::Spiral => angle(x, y) as i32 + (hex_distance(x, y) as i32 * step),
+        _ => 0,
+    };
+    shift as u8
+}
+
+/// Phase shift (in 256ths of a period) of an intra-cell element at a led
+pub fn led_offset(pattern: PatternId, led: &LedPosition, param: u8) -> u8
+{
+    match pattern
+    {
+        PatternId::Sweep => {
+            let shift = if param == 0 { DEFAULT_SWEEP_SHIFT } else { param };
+            ((led.radius as u32 * shift as u32) / MAX_RADIUS as u32) as u8
+        },
+        PatternId::Rotate => {
+            let arms = if param == 0 { DEFAULT_ROTATE_ARMS } else { param };
+            led.angle.wrapping_mul(arms)
+        },
+        _ => 0,
+    }
+}
+
+/// How far a led sits towards edge, 0 on the far rim up to 255 on the near
+/// one. The center is half way.
+pub fn edge_level(led: &LedPosition, edge: u8) -> u8
+{
+    let heading = ((edge as u16 * 256) / EDGE_COUNT as u16) as u8;
+    let towards = (led.x as i32 * sine(heading) as i32 + led.y as i32 * cosine(heading) as i32) / MAX_RADIUS as i32;
+    let span = 2 * MAX_RADIUS as i32;
+    (((towards + MAX_RADIUS as i32).clamp(0, span) * 0xFF) / span) as u8
+}
+
+/// Raised cosine pulse over one period, peaking half way through
+pub fn wave_level(t: u8) -> u8
+{
+    let triangle = if t < 0x80 { t << 1 } else { (0xFF - t) << 1 };
+    Easing::Sine.apply(triangle)
+}
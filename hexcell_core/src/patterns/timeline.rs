@@ -0,0 +1,406 @@
+//! Recorded per-led animations, compressed into time slices.
+//!
+//! A timeline is one led's color sampled every `frame_period`, stored as a
+//! header followed by records. Keyframes carry an absolute color, everything
+//! between them is a delta from the frame before, so a long recording plays
+//! straight out of flash with only a few bytes of state per cursor.
+//!
+//! | record                 | bytes | frames | meaning                                   |
+//! |------------------------|-------|--------|-------------------------------------------|
+//! | `00nnnnnn`             | 1     | n + 1  | HOLD the current color                    |
+//! | `01nnnnnn` delta:u16   | 3     | n + 1  | RAMP, add delta to the color every frame   |
+//! | `10000000` r g b       | 4     | 1      | KEY, an absolute color                    |
+//! | `11111111`             | 1     | 0      | END, also implied by the end of the data  |
+//!
+//! RAMP deltas pack three signed 5 bit steps (-16..15), red in the top bits:
+//! `rrrrrggg ggbbbbb0`, little endian. Channels saturate rather than wrap.
+//! Every timeline starts with a KEY.
+
+use hexcell_api::display::Led;
+use embedded_time::duration::*;
+use zerocopy::{AsBytes, FromBytes, FromZeroes, Unaligned};
+use zerocopy::byteorder::{LittleEndian, U32};
+use crate::hexcore_errors::PatternError;
+
+/// Bump whenever the record layout above changes
+pub const TIMELINE_FORMAT_VERSION: u8 = 1;
+/// Timelines the engine can hold at once
+pub const MAX_TIMELINE_COUNT: usize = 4;
+/// The encoder forces a keyframe at least this often, bounding how far a
+/// corrupted delta can carry
+pub const KEYFRAME_INTERVAL: u32 = 256;
+/// Longest HOLD or RAMP
+pub const MAX_RUN: u32 = 64;
+
+const OP_MASK: u8 = 0xC0;
+const RUN_MASK: u8 = 0x3F;
+const OP_HOLD: u8 = 0x00;
+const OP_RAMP: u8 = 0x40;
+const OP_KEY: u8 = 0x80;
+const OP_END: u8 = 0xFF;
+
+const RAMP_MIN: i16 = -16;
+const RAMP_MAX: i16 = 15;
+
+#[repr(C)]
+#[derive(Copy, Clone, AsBytes, FromBytes, FromZeroes, Unaligned)]
+struct TimelineHeader
+{
+    version: u8,
+    frame_period: U32<LittleEndian>,
+}
+
+pub const TIMELINE_HEADER_SIZE: usize = core::mem::size_of::<TimelineHeader>();
+
+/// Length of the record starting with op, None if op is not a record
+fn record_size(op: u8) -> Option<usize>
+{
+    match op
+    {
+        OP_END => Some(1),
+        OP_KEY => Some(4),
+        _ => match op & OP_MASK
+        {
+            OP_HOLD => Some(1),
+            OP_RAMP => Some(3),
+            _ => None
+        }
+    }
+}
+
+fn pack_delta(delta: [i16; 3]) -> u16
+{
+    let field = |d: i16| (d as u16) & 0x1F;
+    (field(delta[0]) << 11) | (field(delta[1]) << 6) | (field(delta[2]) << 1)
+}
+
+fn unpack_delta(packed: u16) -> [i8; 3]
+{
+    // Shift each field to the top of an i8, then back down to sign extend it
+    let field = |shift: u16| (((packed >> shift) as u8) << 3) as i8 >> 3;
+    [field(11), field(6), field(1)]
+}
+
+fn step_channel(channel: u8, delta: i8, frames: u32) -> u8
+{
+    (channel as i64 + delta as i64 * frames as i64).clamp(0, 255) as u8
+}
+
+/// A validated timeline, borrowed from flash
+#[derive(Copy, Clone, Default)]
+pub struct Timeline
+{
+    data: &'static [u8],
+    frame_period: u32,
+    frames: u32,
+}
+
+impl Timeline
+{
+    /// Checks data is a complete timeline, counting its frames on the way
+    pub fn new(data: &'static [u8]) -> Result<Timeline, PatternError>
+    {
+        let header = match TimelineHeader::read_from_prefix(data)
+        {
+            Some(header) => header,
+            None => return Err(PatternError::TruncatedDataError)
+        };
+        if header.version != TIMELINE_FORMAT_VERSION
+        {
+            return Err(PatternError::UnsupportedVersionError);
+        }
+        if header.frame_period.get() == 0
+        {
+            return Err(PatternError::InvalidPatternError);
+        }
+        let mut offset = TIMELINE_HEADER_SIZE;
+        let mut frames: u32 = 0;
+        while let Some(&op) = data.get(offset)
+        {
+            let size = match record_size(op)
+            {
+                Some(size) => size,
+                None => return Err(PatternError::InvalidOpcodeError)
+            };
+            if offset + size > data.len()
+            {
+                return Err(PatternError::TruncatedDataError);
+            }
+            if frames == 0 && op != OP_KEY
+            {
+                return Err(PatternError::InvalidPatternError);
+            }
+            frames = match op
+            {
+                OP_END => break,
+                OP_KEY => frames.saturating_add(1),
+                _ => frames.saturating_add((op & RUN_MASK) as u32 + 1)
+            };
+            offset += size;
+        }
+        if frames == 0
+        {
+            return Err(PatternError::InvalidPatternError);
+        }
+        Ok(Timeline { data, frame_period: header.frame_period.get(), frames })
+    }
+
+    pub fn frame_period(&self) -> Microseconds<u32>
+    {
+        Microseconds(self.frame_period)
+    }
+
+    pub fn frames(&self) -> u32
+    {
+        self.frames
+    }
+
+    /// Length of one pass
+    pub fn duration(&self) -> Microseconds<u64>
+    {
+        Microseconds(self.frames as u64 * self.frame_period as u64)
+    }
+}
+
+/// Where a cursor is in a timeline, all it needs to produce the next frame
+#[derive(Copy, Clone, Default)]
+pub(crate) struct TimelineReader
+{
+    // Next record to decode
+    offset: usize,
+    color: Led,
+    // Frames left in the current HOLD or RAMP after this one
+    remaining: u8,
+    delta: [i8; 3],
+    // Frames since the first one
+    frame: u32,
+    // Time into the current frame
+    elapsed: u32,
+}
+
+impl TimelineReader
+{
+    pub(crate) fn color(&self) -> Led
+    {
+        self.color
+    }
+
+    /// Back to the first frame
+    pub(crate) fn restart(&mut self, timeline: &Timeline)
+    {
+        *self = TimelineReader { offset: TIMELINE_HEADER_SIZE, ..Default::default() };
+        self.step(timeline);
+        self.frame = 0;
+    }
+
+    /// Time from the start of the pass
+    pub(crate) fn position(&self, timeline: &Timeline) -> u64
+    {
+        self.frame as u64 * timeline.frame_period as u64 + self.elapsed as u64
+    }
+
+    /// Moves on one frame, false once the timeline has ended
+    fn step(&mut self, timeline: &Timeline) -> bool
+    {
+        let stepped = self.decode(timeline);
+        if stepped
+        {
+            self.frame += 1;
+        }
+        stepped
+    }
+
+    fn decode(&mut self, timeline: &Timeline) -> bool
+    {
+        if self.remaining > 0
+        {
+            self.remaining -= 1;
+            self.apply(1);
+            return true;
+        }
+        let data = timeline.data;
+        let op = match data.get(self.offset)
+        {
+            Some(&op) => op,
+            None => return false
+        };
+        match op
+        {
+            OP_END => false,
+            OP_KEY => match data.get(self.offset + 1..self.offset + 4)
+            {
+                Some(rgb) => {
+                    self.color = Led { r: rgb[0], g: rgb[1], b: rgb[2] };
+                    self.delta = [0; 3];
+                    self.offset += 4;
+                    true
+                },
+                None => false
+            },
+            _ if op & OP_MASK == OP_HOLD => {
+                self.delta = [0; 3];
+                self.remaining = op & RUN_MASK;
+                self.offset += 1;
+                true
+            },
+            _ if op & OP_MASK == OP_RAMP => match data.get(self.offset + 1..self.offset + 3)
+            {
+                Some(packed) => {
+                    self.delta = unpack_delta(u16::from_le_bytes([packed[0], packed[1]]));
+                    self.remaining = op & RUN_MASK;
+                    self.offset += 3;
+                    self.apply(1);
+                    true
+                },
+                None => false
+            },
+            _ => false
+        }
+    }
+
+    fn apply(&mut self, frames: u32)
+    {
+        self.color = Led {
+            r: step_channel(self.color.r, self.delta[0], frames),
+            g: step_channel(self.color.g, self.delta[1], frames),
+            b: step_channel(self.color.b, self.delta[2], frames),
+        };
+    }
+
+    /// Moves on by frames, skipping through runs without stepping each one.
+    /// Returns how many frames were left over when the timeline ended.
+    fn skip(&mut self, timeline: &Timeline, mut frames: u32) -> u32
+    {
+        while frames > 0
+        {
+            if self.remaining > 0
+            {
+                let run = frames.min(self.remaining as u32);
+                self.apply(run);
+                self.remaining -= run as u8;
+                self.frame += run;
+                frames -= run;
+            }
+            else
+            {
+                if !self.step(timeline)
+                {
+                    return frames;
+                }
+                frames -= 1;
+            }
+        }
+        0
+    }
+
+    /// Jumps to offset (wrapped to one pass) from the start
+    pub(crate) fn seek(&mut self, timeline: &Timeline, offset: u64)
+    {
+        self.restart(timeline);
+        let duration = timeline.duration().integer();
+        if duration == 0
+        {
+            return;
+        }
+        let offset = offset % duration;
+        self.skip(timeline, (offset / timeline.frame_period as u64) as u32);
+        self.elapsed = (offset % timeline.frame_period as u64) as u32;
+    }
+
+    /// Advances by delta, false once the timeline has played out
+    pub(crate) fn advance(&mut self, timeline: &Timeline, delta: u32, auto_restart: bool) -> bool
+    {
+        let period = timeline.frame_period as u64;
+        if period == 0
+        {
+            return false;
+        }
+        let total = self.elapsed as u64 + delta as u64;
+        self.elapsed = (total % period) as u32;
+        let left = self.skip(timeline, (total / period).min(u32::MAX as u64) as u32);
+        if left == 0
+        {
+            return true;
+        }
+        if !auto_restart
+        {
+            return false;
+        }
+        // The first frame left over starts the next pass
+        let elapsed = self.elapsed as u64;
+        self.seek(timeline, (left as u64 - 1) * period + elapsed);
+        true
+    }
+}
+
+/// Compresses one led's recorded frames into out, returning the bytes used
+pub fn encode_timeline(frames: &[Led], frame_period: Microseconds<u32>, out: &mut [u8]) -> Result<usize, PatternError>
+{
+    if frames.is_empty() || frame_period.integer() == 0
+    {
+        return Err(PatternError::InvalidPatternError);
+    }
+    let mut writer = RecordWriter { out, length: 0 };
+    let header = TimelineHeader { version: TIMELINE_FORMAT_VERSION, frame_period: U32::new(frame_period.integer()) };
+    writer.write(header.as_bytes())?;
+
+    let delta = |from: Led, to: Led| [to.r as i16 - from.r as i16, to.g as i16 - from.g as i16, to.b as i16 - from.b as i16];
+    let mut index = 0;
+    let mut since_key = KEYFRAME_INTERVAL;
+    while index < frames.len()
+    {
+        let frame = frames[index];
+        let step = if index > 0 { delta(frames[index - 1], frame) } else { [0; 3] };
+        let small = step.iter().all(|d| (RAMP_MIN..=RAMP_MAX).contains(d));
+        if since_key >= KEYFRAME_INTERVAL || !small
+        {
+            writer.write(&[OP_KEY, frame.r, frame.g, frame.b])?;
+            since_key = 1;
+            index += 1;
+            continue;
+        }
+        // Extend the run while each frame moves by the same step
+        let limit = MAX_RUN.min(KEYFRAME_INTERVAL - since_key) as usize;
+        let mut run = 1;
+        while run < limit && index + run < frames.len() && delta(frames[index + run - 1], frames[index + run]) == step
+        {
+            run += 1;
+        }
+        let count = (run - 1) as u8;
+        if step == [0; 3]
+        {
+            writer.write(&[OP_HOLD | count])?;
+        }
+        else
+        {
+            let packed = pack_delta(step).to_le_bytes();
+            writer.write(&[OP_RAMP | count, packed[0], packed[1]])?;
+        }
+        since_key += run as u32;
+        index += run;
+    }
+    writer.write(&[OP_END])?;
+    Ok(writer.length)
+}
+
+struct RecordWriter<'a>
+{
+    out: &'a mut [u8],
+    length: usize,
+}
+
+impl RecordWriter<'_>
+{
+    fn write(&mut self, bytes: &[u8]) -> Result<(), PatternError>
+    {
+        match self.out.get_mut(self.length..self.length + bytes.len())
+        {
+            Some(slot) => {
+                slot.copy_from_slice(bytes);
+                self.length += bytes.len();
+                Ok(())
+            },
+            None => Err(PatternError::PatternSizeError)
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! A tiny interpreter so long shows can be expressed as compact programs
+//! instead of flat element lists.
+//!
+//! Programs are little endian bytecode. Each instruction is an opcode byte
+//! followed by fixed size operands:
+//!
+//! | opcode | operands                                                   |
+//! |--------|------------------------------------------------------------|
+//! | HALT   |                                                            |
+//! | PLAY   | pattern u8, register u8, duration u32, easing u8, param u8 |
+//! | COLOR  | register u8, r u8, g u8, b u8                              |
+//! | REPEAT | count u8 (0 repeats forever)                               |
+//! | END    |                                                            |
+//! | JUMP   | address u16                                                |
+//! | RANDOM | chance u8 (out of 256), address u16                        |
+//! | WAIT   | event mask u8                                              |
+//! | CALL   | pattern index u8                                           |
+//!
+//! Running off the end of a program is the same as HALT.
+use hexcell_api::display::Led;
+use embedded_time::duration::*;
+use heapless::Vec;
+use crate::hexcore_errors::PatternError;
+use super::{PatternElement, PatternId};
+use super::easing::Easing;
+
+/// Largest program in bytes
+pub const MAX_PROGRAM_SIZE: usize = 128;
+/// Programs held by the engine at once
+pub const MAX_PROGRAM_COUNT: usize = 4;
+/// Nested REPEAT blocks
+pub const MAX_LOOP_DEPTH: usize = 4;
+pub const COLOR_REGISTERS: usize = 4;
+/// Instructions executed looking for the next element before a program is
+/// considered stuck (e.g. a JUMP to itself)
+const MAX_STEPS: usize = 64;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Opcode
+{
+    Halt = 0x00,
+    Play = 0x01,
+    SetColor = 0x02,
+    Repeat = 0x03,
+    End = 0x04,
+    Jump = 0x05,
+    RandomBranch = 0x06,
+    WaitEvent = 0x07,
+    Call = 0x08,
+}
+
+impl Opcode
+{
+    /// Operand bytes following the opcode
+    const fn operand_size(self) -> usize
+    {
+        match self
+        {
+            Opcode::Halt | Opcode::End => 0,
+            Opcode::Play => 8,
+            Opcode::SetColor => 4,
+            Opcode::Repeat | Opcode::WaitEvent | Opcode::Call => 1,
+            Opcode::Jump => 2,
+            Opcode::RandomBranch => 3,
+        }
+    }
+}
+
+impl TryFrom<u8> for Opcode
+{
+    type Error = PatternError;
+    fn try_from(value: u8) -> Result<Self, Self::Error>
+    {
+        match value
+        {
+            0x00 => Ok(Opcode::Halt),
+            0x01 => Ok(Opcode::Play),
+            0x02 => Ok(Opcode::SetColor),
+            0x03 => Ok(Opcode::Repeat),
+            0x04 => Ok(Opcode::End),
+            0x05 => Ok(Opcode::Jump),
+            0x06 => Ok(Opcode::RandomBranch),
+            0x07 => Ok(Opcode::WaitEvent),
+            0x08 => Ok(Opcode::Call),
+            _ => Err(PatternError::InvalidOpcodeError),
+        }
+    }
+}
+
+/// Validated bytecode
+#[derive(Clone, Default)]
+pub struct Program
+{
+    code: Vec<u8, MAX_PROGRAM_SIZE>
+}
+
+impl Program
+{
+    /// Checks opcodes, operand lengths, jump targets and REPEAT/END nesting
+    pub fn new(code: &[u8]) -> Result<Program, PatternError>
+    {
+        let mut program = Program::default();
+        if program.code.extend_from_slice(code).is_err()
+        {
+            return Err(PatternError::PatternSizeError);
+        }
+
+        // Instruction start addresses and jump targets, one bit per byte of code
+        let mut boundaries: u128 = 0;
+        let mut targets: u128 = 0;
+        let mut add_target = |target: u16| -> Result<(), PatternError> {
+            // Landing exactly on the end halts
+            if (target as usize) < code.len()
+            {
+                targets |= 1 << target;
+                Ok(())
+            }
+            else if target as usize == code.len()
+            {
+                Ok(())
+            }
+            else
+            {
+                Err(PatternError::ProgramBoundsError)
+            }
+        };
+        let mut depth: usize = 0;
+        let mut pc: usize = 0;
+        while pc < code.len()
+        {
+            boundaries |= 1 << pc;
+            let opcode = Opcode::try_from(code[pc])?;
+            let operands = &code[pc + 1..];
+            if operands.len() < opcode.operand_size()
+            {
+                return Err(PatternError::TruncatedDataError);
+            }
+            match opcode
+            {
+                Opcode::Play => {
+                    PatternId::try_from(operands[0])?;
+                    Easing::try_from(operands[6])?;
+                    if operands[1] as usize >= COLOR_REGISTERS
+                    {
+                        return Err(PatternError::InvalidRegisterError);
+                    }
+                },
+                Opcode::SetColor if operands[0] as usize >= COLOR_REGISTERS => {
+                    return Err(PatternError::InvalidRegisterError);
+                },
+                Opcode::Repeat => {
+                    depth += 1;
+                    if depth > MAX_LOOP_DEPTH
+                    {
+                        return Err(PatternError::ProgramStackError);
+                    }
+                },
+                Opcode::End => {
+                    if depth == 0
+                    {
+                        return Err(PatternError::ProgramStackError);
+                    }
+                    depth -= 1;
+                },
+                Opcode::Jump => add_target(read_u16(operands, 0))?,
+                Opcode::RandomBranch => add_target(read_u16(operands, 1))?,
+                _ => {}
+            }
+            pc += 1 + opcode.operand_size();
+        }
+        if depth != 0
+        {
+            return Err(PatternError::ProgramStackError);
+        }
+        // Jumps may only land on an instruction
+        if targets & !boundaries != 0
+        {
+            return Err(PatternError::ProgramBoundsError);
+        }
+        Ok(program)
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.code.is_empty()
+    }
+
+    fn read(&self, at: usize, size: usize) -> Result<&[u8], PatternError>
+    {
+        match self.code.get(at..at + size)
+        {
+            Some(bytes) => Ok(bytes),
+            None => Err(PatternError::ProgramBoundsError)
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16
+{
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+/// Assembles a program one instruction at a time
+pub struct ProgramBuilder
+{
+    code: Vec<u8, MAX_PROGRAM_SIZE>,
+    overflow: bool
+}
+
+impl Default for ProgramBuilder
+{
+    fn default() -> ProgramBuilder
+    {
+        ProgramBuilder::new()
+    }
+}
+
+impl ProgramBuilder
+{
+    pub fn new() -> ProgramBuilder
+    {
+        ProgramBuilder { code: Vec::new(), overflow: false }
+    }
+
+    /// Address of the next instruction, for use as a jump target
+    pub fn position(&self) -> u16
+    {
+        self.code.len() as u16
+    }
+
+    fn emit(mut self, opcode: Opcode, operands: &[u8]) -> ProgramBuilder
+    {
+        if self.code.push(opcode as u8).is_err() || self.code.extend_from_slice(operands).is_err()
+        {
+            self.overflow = true;
+        }
+        self
+    }
+
+    pub fn halt(self) -> ProgramBuilder
+    {
+        self.emit(Opcode::Halt, &[])
+    }
+
+    /// Plays one element in the color held by register
+    pub fn play(self, pattern: PatternId, register: u8, duration: Microseconds<u32>, easing: Easing, param: u8) -> ProgramBuilder
+    {
+        let d = duration.integer().to_le_bytes();
+        self.emit(Opcode::Play, &[pattern as u8, register, d[0], d[1], d[2], d[3], easing as u8, param])
+    }
+
+    pub fn set_color(self, register: u8, color: Led) -> ProgramBuilder
+    {
+        self.emit(Opcode::SetColor, &[register, color.r, color.g, color.b])
+    }
+
+    /// Runs everything up to the matching end count times (0 is forever)
+    pub fn repeat(self, count: u8) -> ProgramBuilder
+    {
+        self.emit(Opcode::Repeat, &[count])
+    }
+
+    pub fn end(self) -> ProgramBuilder
+    {
+        self.emit(Opcode::End, &[])
+    }
+
+    pub fn jump(self, address: u16) -> ProgramBuilder
+    {
+        self.emit(Opcode::Jump, &address.to_le_bytes())
+    }
+
+    /// Jumps to address with a probability of chance/256
+    pub fn random_branch(self, chance: u8, address: u16) -> ProgramBuilder
+    {
+        let a = address.to_le_bytes();
+        self.emit(Opcode::RandomBranch, &[chance, a[0], a[1]])
+    }
+
+    /// Holds the last color until any event in mask is raised
+    pub fn wait_event(self, mask: u8) -> ProgramBuilder
+    {
+        self.emit(Opcode::WaitEvent, &[mask])
+    }
+
+    /// Plays one pass of an engine pattern, then continues
+    pub fn call(self, pattern_idx: u8) -> ProgramBuilder
+    {
+        self.emit(Opcode::Call, &[pattern_idx])
+    }
+
+    pub fn finish(self) -> Result<Program, PatternError>
+    {
+        if self.overflow
+        {
+            return Err(PatternError::PatternSizeError);
+        }
+        Program::new(&self.code)
+    }
+}
+
+/// What a program asks of its cursor next
+#[derive(Copy, Clone)]
+pub enum VmAction
+{
+    Play(PatternElement),
+    Call(usize),
+    Wait(u8),
+    Halt,
+}
+
+#[derive(Copy, Clone, Default)]
+struct LoopFrame
+{
+    start: u16,
+    // Passes left after the current one
+    remaining: u8,
+    forever: bool,
+}
+
+/// Per cursor interpreter state, so cursors can share a program
+#[derive(Copy, Clone, Default)]
+pub struct VmState
+{
+    pc: u16,
+    loops: [LoopFrame; MAX_LOOP_DEPTH],
+    depth: u8,
+    registers: [Led; COLOR_REGISTERS],
+    rng: u32,
+    /// Element being played, None when the next must be fetched
+    pub(crate) element: Option<PatternElement>,
+    /// Playing a called pattern rather than the program
+    pub(crate) calling: bool,
+    /// Events that will wake a WAIT, 0 when not waiting
+    pub(crate) wait_mask: u8,
+    /// Error that stopped the program, if any
+    pub(crate) fault: Option<PatternError>,
+}
+
+impl VmState
+{
+    pub fn new(seed: u32) -> VmState
+    {
+        let mut state = VmState::default();
+        state.seed(seed);
+        state
+    }
+
+    pub fn seed(&mut self, seed: u32)
+    {
+        // Xorshift must not start at zero
+        self.rng = if seed == 0 { 0x9E37_79B9 } else { seed };
+    }
+
+    /// Back to the first instruction, registers and random state are kept
+    pub fn reset(&mut self)
+    {
+        self.pc = 0;
+        self.depth = 0;
+        self.element = None;
+        self.calling = false;
+        self.wait_mask = 0;
+        self.fault = None;
+    }
+
+    fn random(&mut self) -> u32
+    {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+
+    /// Executes instructions until the program produces an action
+    pub fn step(&mut self, program: &Program) -> Result<VmAction, PatternError>
+    {
+        for _ in 0..MAX_STEPS
+        {
+            let at = self.pc as usize;
+            if at >= program.len()
+            {
+                return Ok(VmAction::Halt);
+            }
+            let opcode = Opcode::try_from(program.code[at])?;
+            let operands = program.read(at + 1, opcode.operand_size())?;
+            self.pc = (at + 1 + opcode.operand_size()) as u16;
+            match opcode
+            {
+                Opcode::Halt => {
+                    self.pc = at as u16;
+                    return Ok(VmAction::Halt);
+                },
+                Opcode::Play => {
+                    let register = operands[1] as usize;
+                    let color = match self.registers.get(register)
+                    {
+                        Some(color) => *color,
+                        None => return Err(PatternError::InvalidRegisterError)
+                    };
+                    return Ok(VmAction::Play(PatternElement {
+                        pattern: PatternId::try_from(operands[0])?,
+                        color,
+                        duration: Microseconds(u32::from_le_bytes([operands[2], operands[3], operands[4], operands[5]])),
+                        easing: Easing::try_from(operands[6])?,
+                        param: operands[7],
+                    }));
+                },
+                Opcode::SetColor => {
+                    match self.registers.get_mut(operands[0] as usize)
+                    {
+                        Some(register) => *register = Led { r: operands[1], g: operands[2], b: operands[3] },
+                        None => return Err(PatternError::InvalidRegisterError)
+                    }
+                },
+                Opcode::Repeat => {
+                    let depth = self.depth as usize;
+                    if depth >= MAX_LOOP_DEPTH
+                    {
+                        return Err(PatternError::ProgramStackError);
+                    }
+                    self.loops[depth] = LoopFrame {
+                        start: self.pc,
+                        remaining: operands[0].saturating_sub(1),
+                        forever: operands[0] == 0,
+                    };
+                    self.depth += 1;
+                },
+                Opcode::End => {
+                    if self.depth == 0
+                    {
+                        return Err(PatternError::ProgramStackError);
+                    }
+                    let frame = &mut self.loops[self.depth as usize - 1];
+                    if frame.forever
+                    {
+                        self.pc = frame.start;
+                    }
+                    else if frame.remaining > 0
+                    {
+                        frame.remaining -= 1;
+                        self.pc = frame.start;
+                    }
+                    else
+                    {
+                        self.depth -= 1;
+                    }
+                },
+                Opcode::Jump => {
+                    self.pc = read_u16(operands, 0);
+                },
+                Opcode::RandomBranch => {
+                    if (self.random() & 0xFF) < operands[0] as u32
+                    {
+                        self.pc = read_u16(operands, 1);
+                    }
+                },
+                Opcode::WaitEvent => {
+                    return Ok(VmAction::Wait(operands[0]));
+                },
+                Opcode::Call => {
+                    return Ok(VmAction::Call(operands[0] as usize));
+                },
+            }
+        }
+        Err(PatternError::ProgramStallError)
+    }
+}
@@ -34,3 +34,13 @@ pub const RANKED_PORT: [HardPort; PORT_COUNT] = [
     HardPort::PORT_B,
     HardPort::PORT_F,
 ];
+
+// Maps port => hex edge, numbered clockwise from the top edge
+pub const PORT_EDGES: [u8; PORT_COUNT] = [
+    0, // PORT_A
+    1, // PORT_B
+    4, // PORT_C
+    5, // PORT_D
+    2, // PORT_E
+    3, // PORT_F
+];
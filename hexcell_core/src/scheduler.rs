@@ -1,10 +1,9 @@
 use embedded_time::duration::*;
 use heapless::spsc::Queue;
-use core::cell::{RefCell, UnsafeCell};
-use hexcell_api::timer::Timer32;
+use hexcell_api::timer::{Timer64, Timestamp};
 
 const MAX_TASKS:usize = 32;
-type TaskCallback = dyn FnMut();
+type TaskCallback = fn();
 
 struct TaskData
 {
@@ -33,54 +32,46 @@ impl TaskData
     }
 }
 
-static mut GLOBAL_SCHEDULER: Scheduler = Scheduler::new();
-
-pub fn global_scheduler() -> RefCell<Scheduler>
-{
-    let r = unsafe {RefCell::new(GLOBAL_SCHEDULER)};
-    r
-}
-
 pub struct Scheduler
 {
-    task_queue: Queue<&TaskData, MAX_TASKS>,
-    last_tick: Microseconds<u32>,
+    task_queue: Queue<TaskData, MAX_TASKS>,
+    last_tick: Timestamp,
 }
 
 impl Scheduler
 {
-    pub fn new() -> Scheduler
+    pub const fn new() -> Scheduler
     {
-        Scheduler { task_queue: Queue::<TaskData, MAX_TASKS>::new(), last_tick: Microseconds(0) }
+        Scheduler { task_queue: Queue::<TaskData, MAX_TASKS>::new(), last_tick: Timestamp::ZERO }
     }
     
-    pub fn init(&mut self, now: Microseconds<u32>)
+    pub fn init(&mut self, now: Timestamp)
     {
         self.last_tick = now
     }
 
-    pub fn run(&mut self, timer: &dyn Timer32)
+    pub fn run(&mut self, timer: &dyn Timer64)
     {
         loop {
-            let delta = timer.now() - self.last_tick;
-            self.last_tick = timer.now();
-            if let Some(task) = self.task_queue.dequeue()
+            let now = timer.now64();
+            let delta = now.delta(self.last_tick);
+            self.last_tick = now;
+            if let Some(mut task) = self.task_queue.dequeue()
             {
                 if task.pending
                 {
-                    self.task_queue.enqueue(task);
+                    let _ = self.task_queue.enqueue(task);
                 }
                 else
                 {
-                    task.elapsed = task.elapsed + delta;
+                    task.elapsed = Microseconds(task.elapsed.integer().saturating_add(delta.integer()));
                     if task.elapsed >= task.period
                     {
-                        let mut callee = task.callee.borrow_mut();
-                        callee.task_callback();
+                        (task.callee)();
                         if task.auto_reload
                         {
                             task.elapsed = Microseconds(0);
-                            self.task_queue.enqueue(task);
+                            let _ = self.task_queue.enqueue(task);
                         }
                     }
                 }
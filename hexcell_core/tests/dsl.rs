@@ -0,0 +1,120 @@
+#![cfg(feature = "std")]
+
+use embedded_time::duration::*;
+use hexcell_api::display::Led;
+use hexcell_api::messaging::MessageBuffer;
+use hexcell_core::patterns::dsl::{parse, to_text, ParseError, ParseErrorKind};
+use hexcell_core::patterns::easing::Easing;
+use hexcell_core::patterns::{Pattern, PatternBuilder, PatternElement, PatternId, MAX_PATTERN_ELEMENTS};
+
+fn bytes(pattern: &Pattern) -> std::vec::Vec<u8>
+{
+    let mut buffer = MessageBuffer::new();
+    pattern.encode(&mut buffer).unwrap();
+    buffer.to_vec()
+}
+
+fn error(text: &str) -> ParseError
+{
+    match parse(text)
+    {
+        Ok(_) => panic!("Parsed {:?}", text),
+        Err(error) => error
+    }
+}
+
+fn at(line: usize, column: usize, kind: ParseErrorKind) -> ParseError
+{
+    ParseError { line, column, kind }
+}
+
+#[test]
+fn settings_parse_in_any_order()
+{
+    let text = "fade #ff0000 1s ease-in; blink 500ms #00ff00 x3\n\
+                wave-x param=32 #0000ff 2.5s   // pulse crossing the hive\n\
+                \n\
+                solid 40us";
+    let element = |pattern, color, micros, easing, param| PatternElement { pattern, color, duration: Microseconds(micros), easing, param };
+    let green = element(PatternId::Blink, Led { r: 0, g: 255, b: 0 }, 500_000, Easing::Linear, 0);
+    let expected = PatternBuilder::new()
+        .then(element(PatternId::Fade, Led { r: 255, g: 0, b: 0 }, 1_000_000, Easing::EaseIn, 0))
+        .then(green)
+        .then(green)
+        .then(green)
+        .then(element(PatternId::WaveX, Led { r: 0, g: 0, b: 255 }, 2_500_000, Easing::Linear, 32))
+        .then(element(PatternId::Solid, Led::default(), 40, Easing::Linear, 0))
+        .finish();
+    assert_eq!(bytes(&parse(text).unwrap()), bytes(&expected));
+}
+
+#[test]
+fn durations_take_fractions_and_units()
+{
+    let micros = |text: &str| parse(&std::format!("solid {}", text)).unwrap().duration().integer();
+    assert_eq!(micros("2s"), 2_000_000);
+    assert_eq!(micros("1.25s"), 1_250_000);
+    assert_eq!(micros("0.5ms"), 500);
+    assert_eq!(micros("12us"), 12);
+    // Digits past the microsecond are dropped
+    assert_eq!(micros("1.0000009s"), 1_000_000);
+}
+
+#[test]
+fn errors_point_at_the_offending_word()
+{
+    assert_eq!(error("glow #ff0000 1s"), at(1, 1, ParseErrorKind::UnknownPattern));
+    assert_eq!(error("solid 1s; blink 1s loud"), at(1, 20, ParseErrorKind::UnknownSetting));
+    assert_eq!(error("solid 1s\n  fade #ff00 1s"), at(2, 8, ParseErrorKind::InvalidColor));
+    assert_eq!(error("solid #ffgg00 1s"), at(1, 7, ParseErrorKind::InvalidColor));
+    assert_eq!(error("solid 1m"), at(1, 7, ParseErrorKind::InvalidDuration));
+    assert_eq!(error("solid .5s"), at(1, 7, ParseErrorKind::UnknownSetting));
+    assert_eq!(error("solid 99999s"), at(1, 7, ParseErrorKind::InvalidDuration));
+    assert_eq!(error("solid 1s\n\n    fade #ff0000"), at(3, 5, ParseErrorKind::MissingDuration));
+    assert_eq!(error("solid 1s x0"), at(1, 10, ParseErrorKind::InvalidCount));
+    assert_eq!(error("solid 1s param=256"), at(1, 10, ParseErrorKind::InvalidParam));
+    assert_eq!(error("solid 1s 2s"), at(1, 10, ParseErrorKind::DuplicateSetting));
+    assert_eq!(error("solid 1s sine cubic"), at(1, 15, ParseErrorKind::DuplicateSetting));
+    assert_eq!(error(&std::format!("solid 1s; blink 1s x{}", MAX_PATTERN_ELEMENTS)), at(1, 11, ParseErrorKind::TooManyElements));
+    // Reported after the last line
+    assert_eq!(error("// nothing here\n  ;"), at(2, 4, ParseErrorKind::EmptyPattern));
+    assert_eq!(error("solid 1m").to_string(), "1:7: duration must be a number followed by s, ms or us");
+}
+
+#[test]
+fn pretty_printing_round_trips()
+{
+    let text = "fade #ff0000 1s ease-in;\n\
+                blink #00ff00 500ms x3;\n\
+                wave-x #0000ff 2500ms param=32;\n\
+                solid #000000 40us;\n\
+                edge #ffff00 2s ease-in-out param=1;\n";
+    let pattern = parse(text).unwrap();
+    assert_eq!(to_text(&pattern), text);
+    assert_eq!(bytes(&parse(&to_text(&pattern)).unwrap()), bytes(&pattern));
+}
+
+#[test]
+fn every_pattern_and_easing_prints_back_the_same()
+{
+    let mut builder = PatternBuilder::new();
+    for id in 0..19
+    {
+        builder = builder.then(PatternElement {
+            pattern: PatternId::try_from(id).unwrap(),
+            color: Led { r: id, g: 255 - id, b: 7 },
+            duration: Microseconds(1_000 + id as u32),
+            easing: Easing::try_from(id % 6).unwrap(),
+            param: id * 13,
+        });
+        // More ids than a pattern holds, so split them in two
+        if id == 9
+        {
+            let pattern = builder.finish();
+            assert_eq!(bytes(&parse(&to_text(&pattern)).unwrap()), bytes(&pattern));
+            builder = PatternBuilder::new();
+        }
+    }
+    let pattern = builder.finish();
+    assert_eq!(bytes(&parse(&to_text(&pattern)).unwrap()), bytes(&pattern));
+}
@@ -0,0 +1,39 @@
+use hexcell_core::patterns::easing::Easing;
+
+const CURVES: [Easing; 6] = [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut, Easing::Sine, Easing::Cubic];
+
+#[test]
+fn curves_start_and_end_on_their_targets()
+{
+    for easing in CURVES
+    {
+        assert_eq!((easing.apply(0), easing.apply(0xFF)), (0, 0xFF), "{:?}", easing);
+        assert_eq!((easing.apply_wide(0), easing.apply_wide(0xFFFF)), (0, 0xFFFF), "{:?}", easing);
+    }
+}
+
+#[test]
+fn curves_never_turn_back()
+{
+    for easing in CURVES
+    {
+        for t in 1..=0xFFu8
+        {
+            assert!(easing.apply(t) >= easing.apply(t - 1), "{:?} at {}", easing, t);
+        }
+        for t in 1..=0xFFFFu16
+        {
+            assert!(easing.apply_wide(t) >= easing.apply_wide(t - 1), "{:?} at {}", easing, t);
+        }
+    }
+}
+
+#[test]
+fn every_curve_is_listed()
+{
+    for (code, easing) in CURVES.iter().enumerate()
+    {
+        assert_eq!(Easing::try_from(code as u8).ok(), Some(*easing));
+    }
+    assert!(Easing::try_from(CURVES.len() as u8).is_err());
+}
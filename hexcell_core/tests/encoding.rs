@@ -0,0 +1,111 @@
+use embedded_time::duration::*;
+use hexcell_api::display::Led;
+use hexcell_api::messaging::MessageBuffer;
+use hexcell_core::hexcore_errors::PatternError;
+use hexcell_core::patterns::easing::Easing;
+use hexcell_core::patterns::encoding::{ENCODED_PATTERN_MAX_SIZE, PATTERN_ELEMENT_SIZE, PATTERN_FORMAT_VERSION, PATTERN_HEADER_SIZE};
+use hexcell_core::patterns::{Pattern, PatternBuilder, PatternElement, PatternId, MAX_PATTERN_ELEMENTS};
+
+fn sample() -> Pattern
+{
+    PatternBuilder::new()
+        .then(PatternElement { pattern: PatternId::Fade, color: Led { r: 0x12, g: 0x34, b: 0x56 }, duration: Microseconds(0x0102_0304), easing: Easing::Sine, param: 9 })
+        .then(PatternElement { pattern: PatternId::EdgeGradient, color: Led { r: 255, g: 0, b: 128 }, duration: Microseconds(250_000), easing: Easing::Linear, param: 0 })
+        .finish()
+}
+
+fn encode(pattern: &Pattern) -> std::vec::Vec<u8>
+{
+    let mut buffer = MessageBuffer::new();
+    pattern.encode(&mut buffer).unwrap();
+    buffer.to_vec()
+}
+
+fn rejects(bytes: &[u8]) -> PatternError
+{
+    match Pattern::decode(bytes)
+    {
+        Ok(_) => panic!("Decoded {:02x?}", bytes),
+        Err(error) => error
+    }
+}
+
+#[test]
+fn patterns_encode_to_the_wire_layout()
+{
+    let bytes = encode(&sample());
+    assert_eq!(bytes.len(), PATTERN_HEADER_SIZE + 2 * PATTERN_ELEMENT_SIZE);
+    assert_eq!(bytes, [
+        PATTERN_FORMAT_VERSION, 2,
+        2, 0x12, 0x34, 0x56, 0x04, 0x03, 0x02, 0x01, 4, 9,
+        18, 255, 0, 128, 0x90, 0xD0, 0x03, 0x00, 0, 0,
+    ]);
+}
+
+#[test]
+fn patterns_survive_a_round_trip()
+{
+    let bytes = encode(&sample());
+    let decoded = Pattern::decode(&bytes).unwrap();
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(encode(&decoded), bytes);
+    // Encoding replaces whatever the buffer held
+    let mut buffer = MessageBuffer::new();
+    buffer.extend_from_slice(&[0xAA; 7]).unwrap();
+    sample().encode(&mut buffer).unwrap();
+    assert_eq!(buffer.to_vec(), bytes);
+}
+
+#[test]
+fn full_patterns_fit_a_message()
+{
+    let element = PatternElement { pattern: PatternId::Blink, duration: Microseconds(u32::MAX), ..Default::default() };
+    let mut builder = PatternBuilder::new();
+    for _ in 0..MAX_PATTERN_ELEMENTS
+    {
+        builder = builder.then(element);
+    }
+    let pattern = builder.finish();
+    // Longer than a u32 of microseconds holds
+    assert_eq!(pattern.duration(), Microseconds(u32::MAX));
+    let bytes = encode(&pattern);
+    assert_eq!(bytes.len(), ENCODED_PATTERN_MAX_SIZE);
+    assert_eq!(Pattern::decode(&bytes).unwrap().len(), MAX_PATTERN_ELEMENTS);
+}
+
+#[test]
+fn unknown_ids_and_versions_are_rejected()
+{
+    let bytes = encode(&sample());
+    let mut unknown_pattern = bytes.clone();
+    unknown_pattern[PATTERN_HEADER_SIZE] = 0xEE;
+    assert!(matches!(rejects(&unknown_pattern), PatternError::UnknownPatternIdError));
+    let mut unknown_easing = bytes.clone();
+    unknown_easing[PATTERN_HEADER_SIZE + PATTERN_ELEMENT_SIZE + 8] = 6;
+    assert!(matches!(rejects(&unknown_easing), PatternError::UnknownEasingError));
+    let mut old = bytes.clone();
+    old[0] = PATTERN_FORMAT_VERSION - 1;
+    assert!(matches!(rejects(&old), PatternError::UnsupportedVersionError));
+    let mut newer = bytes;
+    newer[0] = PATTERN_FORMAT_VERSION + 1;
+    assert!(matches!(rejects(&newer), PatternError::UnsupportedVersionError));
+}
+
+#[test]
+fn wrong_lengths_are_rejected()
+{
+    let bytes = encode(&sample());
+    // Short header, a missing element and a partial one
+    assert!(matches!(rejects(&[]), PatternError::TruncatedDataError));
+    assert!(matches!(rejects(&bytes[..1]), PatternError::TruncatedDataError));
+    assert!(matches!(rejects(&bytes[..PATTERN_HEADER_SIZE + PATTERN_ELEMENT_SIZE]), PatternError::TruncatedDataError));
+    assert!(matches!(rejects(&bytes[..bytes.len() - 1]), PatternError::TruncatedDataError));
+    assert!(matches!(PatternElement::decode(&bytes[PATTERN_HEADER_SIZE..PATTERN_HEADER_SIZE + 5]), Err(PatternError::TruncatedDataError)));
+    // Trailing bytes, and more elements than a pattern holds
+    let mut long = bytes.clone();
+    long.push(0);
+    assert!(matches!(rejects(&long), PatternError::InvalidPatternError));
+    assert!(matches!(rejects(&[PATTERN_FORMAT_VERSION, MAX_PATTERN_ELEMENTS as u8 + 1]), PatternError::PatternSizeError));
+    // An empty pattern is still a pattern
+    assert!(Pattern::decode(&[PATTERN_FORMAT_VERSION, 0]).unwrap().is_empty());
+}
@@ -0,0 +1,120 @@
+use core::cell::RefCell;
+use embedded_time::duration::*;
+use hexcell_api::display::{Led, LED_COUNT};
+use hexcell_api::timer::Timestamp;
+use hexcell_core::hexcore::HexCellCore;
+use hexcell_core::patterns::{CursorEvent, Pattern, PatternBuilder, PatternElement, PatternEngine, PatternEvent, PatternId, MAX_LAYERS};
+use hexcell_core::scheduler::Scheduler;
+
+const RED: Led = Led { r: 255, g: 0, b: 0 };
+const GREEN: Led = Led { r: 0, g: 255, b: 0 };
+
+fn two_steps(first: Led, second: Led) -> Pattern
+{
+    let solid = |color| PatternElement { pattern: PatternId::Solid, color, duration: Microseconds(100_000), ..Default::default() };
+    PatternBuilder::new().then(solid(first)).then(solid(second)).finish()
+}
+
+fn drain(engine: &mut PatternEngine) -> std::vec::Vec<PatternEvent>
+{
+    core::iter::from_fn(|| engine.poll_event()).collect()
+}
+
+#[test]
+fn cursors_report_elements_loops_and_finishing()
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, two_steps(RED, GREEN)).unwrap();
+    engine.play_pattern_once(0, 0).unwrap();
+    engine.set_layer_cursor_to_pattern(0, 1, 0, true).unwrap();
+
+    engine.run(Microseconds(50_000));
+    assert!(drain(&mut engine).is_empty());
+
+    engine.run(Microseconds(100_000));
+    let events = drain(&mut engine);
+    assert_eq!(events.len(), LED_COUNT);
+    assert!(events.iter().all(|e| e.layer == 0 && e.event == CursorEvent::Element(1)));
+
+    engine.run(Microseconds(100_000));
+    let events = drain(&mut engine);
+    assert_eq!(events.iter().filter(|e| e.event == CursorEvent::Finished).count(), LED_COUNT - 1);
+    assert_eq!(events.iter().find(|e| e.cursor == 1).map(|e| e.event), Some(CursorEvent::Looped));
+    assert!(engine.layer_active(0));
+
+    // Finished cursors stay quiet
+    engine.run(Microseconds(100_000));
+    assert_eq!(drain(&mut engine).len(), 1);
+}
+
+#[test]
+fn base_layer_finishing_survives_a_full_queue()
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, two_steps(RED, GREEN)).unwrap();
+    engine.play_pattern_once(0, 0).unwrap();
+    // Overlays looping every tick, with nobody polling
+    engine.set_pattern(1, two_steps(GREEN, RED)).unwrap();
+    for layer in 1..3
+    {
+        engine.enable_layer(layer, true).unwrap();
+        engine.play_pattern(layer, 1, false).unwrap();
+    }
+    for _ in 0..4
+    {
+        engine.run(Microseconds(100_000));
+    }
+    assert!(!engine.layer_active(0));
+    assert!(engine.take_base_finished());
+    assert!(!engine.take_base_finished());
+}
+
+#[test]
+fn a_tick_of_events_from_every_layer_fits()
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, two_steps(RED, GREEN)).unwrap();
+    for layer in 0..MAX_LAYERS
+    {
+        engine.enable_layer(layer, true).unwrap();
+        engine.play_pattern_once(layer, 0).unwrap();
+    }
+    // Every cursor moves on, then every cursor finishes
+    engine.run(Microseconds(100_000));
+    assert_eq!(drain(&mut engine).len(), MAX_LAYERS * LED_COUNT);
+    engine.run(Microseconds(100_000));
+    let events = drain(&mut engine);
+    assert_eq!(events.len(), MAX_LAYERS * LED_COUNT);
+    for layer in 0..MAX_LAYERS
+    {
+        assert_eq!(events.iter().filter(|e| e.layer == layer && e.event == CursorEvent::Finished).count(), LED_COUNT);
+    }
+}
+
+#[test]
+fn chained_shows_follow_on_and_the_last_one_reports()
+{
+    let scheduler = RefCell::new(Scheduler::new());
+    let mut core = HexCellCore::new(&scheduler);
+    core.init(Timestamp::ZERO);
+    core.set_uid(0xC0FFEE);
+    core.play_show(two_steps(RED, RED), false).unwrap();
+    core.chain_show(two_steps(GREEN, GREEN), false);
+
+    let mut now = 0;
+    let mut tick = |core: &mut HexCellCore, step: u64| {
+        now += step;
+        core.tick(Timestamp::from_micros(now));
+        core.pattern_buffer()[0]
+    };
+    assert_eq!(tick(&mut core, 100_000), RED);
+    // The first show ends on this tick and the chained one takes over
+    assert_eq!(tick(&mut core, 100_000), RED);
+    assert!(!core.take_show_finished());
+    assert_eq!(tick(&mut core, 50_000), GREEN);
+    tick(&mut core, 50_000);
+    assert!(!core.take_show_finished());
+    tick(&mut core, 100_000);
+    assert!(core.take_show_finished());
+    assert!(!core.take_show_finished());
+}
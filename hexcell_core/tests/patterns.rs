@@ -0,0 +1,396 @@
+use embedded_time::duration::*;
+use hexcell_api::display::{Hsv, Led, WideLed, LED_COUNT, LED_GEOMETRY, MAX_RADIUS};
+use hexcell_core::networking::NetworkId;
+use hexcell_core::patterns::spatial::hex_distance;
+use hexcell_core::patterns::{BlendMode, PatternBuilder, PatternElement, PatternEngine, PatternId};
+use hexcell_core::ports::HardPort;
+
+const WHITE: Led = Led { r: 255, g: 255, b: 255 };
+const OFF: Led = Led { r: 0, g: 0, b: 0 };
+
+fn single_element_engine(pattern: PatternId, color: Led, duration: u32) -> PatternEngine
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, PatternBuilder::new()
+        .then(PatternElement { pattern, color, duration: Microseconds(duration), ..Default::default() })
+        .finish()).unwrap();
+    engine.start();
+    engine.set_cursor_to_pattern(0, 0, true).expect("Invalid cursor setting");
+    engine
+}
+
+#[test]
+fn heartbeat_double_pulse()
+{
+    // 256ms element, sampled every 8ms (8/256ths of the envelope)
+    let mut engine = single_element_engine(PatternId::Heartbeat, WHITE, 256_000);
+    let levels: [u8; 32] = [
+        63, 127, 191, 255, 192, 128, 64, 0,
+        40, 80, 120, 160, 120, 80, 40, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    for (step, level) in levels.iter().enumerate()
+    {
+        let output = engine.run(Microseconds(8_000));
+        assert_eq!(output[0], WHITE.scale(*level), "step {}", step);
+    }
+    // Restarted: the first pulse begins again
+    let output = engine.run(Microseconds(8_000));
+    assert_eq!(output[0], WHITE.scale(63));
+}
+
+#[test]
+fn heartbeat_scales_color()
+{
+    let color = Led { r: 200, g: 100, b: 0 };
+    let mut engine = single_element_engine(PatternId::Heartbeat, color, 1_000_000);
+    // 1/8th through the element is the peak of the first beat
+    let output = engine.run(Microseconds(125_000));
+    assert_eq!(output[0], color.scale(255));
+    // 3/8ths is the peak of the second beat
+    let output = engine.run(Microseconds(250_000));
+    assert_eq!(output[0], color.scale(160));
+    let output = engine.run(Microseconds(250_000));
+    assert_eq!(output[0], OFF);
+}
+
+#[test]
+fn sos_morse_timing()
+{
+    // 34 units of 1ms each, sampled twice per unit
+    let color = Led { r: 0, g: 0, b: 255 };
+    let mut engine = single_element_engine(PatternId::SOS, color, 34_000);
+    let sequence = b"1010100011101110111000101010000000";
+    for step in 1..=68
+    {
+        let output = engine.run(Microseconds(500));
+        let unit = core::cmp::min(step * 500 / 1_000, sequence.len() - 1);
+        let expected = if sequence[unit] == b'1' { color } else { OFF };
+        assert_eq!(output[0], expected, "step {}", step);
+    }
+    // Sequence repeats from the first dot
+    let output = engine.run(Microseconds(500));
+    assert_eq!(output[0], color);
+}
+
+#[test]
+fn sos_one_shot_ends_dark()
+{
+    let mut engine = single_element_engine(PatternId::SOS, WHITE, 3_400_000);
+    engine.set_cursor_to_pattern(0, 0, false).expect("Invalid cursor setting");
+    let output = engine.run(Microseconds(3_400_000));
+    assert_eq!(output[0], OFF);
+    let output = engine.run(Microseconds(50_000));
+    assert_eq!(output[0], OFF);
+}
+
+fn seeded_engine(pattern: PatternId, seed: u32) -> PatternEngine
+{
+    let mut engine = single_element_engine(pattern, WHITE, 1_000_000);
+    engine.set_seed(seed);
+    engine
+}
+
+#[test]
+fn generators_replay_identically_for_a_seed()
+{
+    for pattern in [PatternId::Noise, PatternId::Fire, PatternId::Twinkle, PatternId::Sparkle]
+    {
+        let mut coarse = seeded_engine(pattern, 0x1234_5678);
+        let mut fine = seeded_engine(pattern, 0x1234_5678);
+        // Frames depend on time alone, not on how it was ticked. Compared
+        // before dithering, which does follow the ticks.
+        for step in 0..300
+        {
+            let _ = fine.run(Microseconds(5_000));
+            let _ = fine.run(Microseconds(5_000));
+            let _ = coarse.run(Microseconds(10_000));
+            assert!(coarse.get_wide_output_buffer() == fine.get_wide_output_buffer(), "step {}", step);
+        }
+    }
+}
+
+#[test]
+fn generators_differ_between_cells_and_leds()
+{
+    for pattern in [PatternId::Noise, PatternId::Fire, PatternId::Twinkle, PatternId::Sparkle]
+    {
+        let mut a = seeded_engine(pattern, 1);
+        let mut b = seeded_engine(pattern, 2);
+        let (mut between_cells, mut between_leds) = (false, false);
+        for _ in 0..300
+        {
+            let (out_a, out_b) = (a.run(Microseconds(10_000)), b.run(Microseconds(10_000)));
+            between_cells |= out_a != out_b;
+            between_leds |= out_a.iter().any(|led| *led != out_a[0]);
+        }
+        assert!(between_cells && between_leds);
+    }
+}
+
+// Outer ring led at angle, which every board has for multiples of a sixth of a turn
+fn rim_led(angle: u8) -> usize
+{
+    LED_GEOMETRY.iter().position(|led| led.radius == MAX_RADIUS && led.angle == angle).expect("No led at angle")
+}
+
+#[test]
+fn rotate_turns_clockwise_around_the_cell()
+{
+    let mut engine = single_element_engine(PatternId::Rotate, WHITE, 600_000);
+    let rim: Vec<usize> = (0..LED_COUNT).filter(|&led| LED_GEOMETRY[led].radius == MAX_RADIUS).collect();
+    let mut brightest = [0u8; 6];
+    for step in brightest.iter_mut()
+    {
+        let frame = engine.run(Microseconds(100_000));
+        let led = *rim.iter().max_by_key(|&&led| frame[led].r).unwrap();
+        *step = LED_GEOMETRY[led].angle;
+    }
+    // Sixth of a turn per frame, passing the top edge half way through
+    assert_eq!(brightest, [170, 213, 0, 42, 85, 128]);
+}
+
+#[test]
+fn edge_gradient_fades_in_from_one_edge()
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, PatternBuilder::new()
+        .then(PatternElement { pattern: PatternId::EdgeGradient, color: WHITE, duration: Microseconds(100_000), param: 2, ..Default::default() })
+        .then(PatternElement { pattern: PatternId::Solid, color: OFF, duration: Microseconds(100_000), ..Default::default() })
+        .finish()).unwrap();
+    engine.start();
+    engine.set_cursor_to_pattern(0, 0, true).expect("Invalid cursor setting");
+    let frame = engine.run(Microseconds(99_999));
+    // Edge 2 is a third of a turn round, edge 5 opposite it
+    let (near, far) = (rim_led(85), rim_led(213));
+    assert_eq!(frame[near], WHITE);
+    assert_eq!(frame[far], OFF);
+    assert!(frame[0].r > frame[far].r && frame[0].r < frame[near].r);
+}
+
+#[test]
+fn slow_dim_fades_move_between_steps()
+{
+    // Only four 8 bit steps, spread over a second
+    let dim = Led { r: 4, g: 4, b: 4 };
+    let mut engine = single_element_engine(PatternId::Fade, dim, 1_000_000);
+    let (mut previous, mut levels) = (0, 0);
+    for _ in 0..99
+    {
+        let output = engine.run(Microseconds(10_000));
+        let wide = engine.get_wide_output_buffer()[0].r;
+        assert!(wide >= previous);
+        levels += (wide != previous) as u32;
+        previous = wide;
+        // Rounded to one of the steps either side
+        let step = wide as u32 / 257;
+        assert!(output[0].r as u32 == step || output[0].r as u32 == step + 1, "{} shown as {}", wide, output[0].r);
+    }
+    assert!(levels > 50, "only {} levels", levels);
+}
+
+#[test]
+fn neighbours_are_one_cell_away()
+{
+    let root = NetworkId::default();
+    assert_eq!(hex_distance(root.x(), root.y()), 0);
+    for port in [HardPort::PORT_A, HardPort::PORT_B, HardPort::PORT_C, HardPort::PORT_D, HardPort::PORT_E, HardPort::PORT_F]
+    {
+        let cell = root.compute_external_id(port);
+        assert_eq!(hex_distance(cell.x(), cell.y()), 1, "({}, {})", cell.x(), cell.y());
+    }
+    // Two columns over, or two rows, is the next ring
+    assert_eq!(hex_distance(2, 0), 2);
+    assert_eq!(hex_distance(-2, -1), 2);
+    assert_eq!(hex_distance(0, -2), 2);
+    assert_eq!(hex_distance(1, 2), 2);
+}
+
+#[test]
+fn overrides_pause_the_show_beneath_until_they_end()
+{
+    let (red, green, blue) = (Led { r: 255, g: 0, b: 0 }, Led { r: 0, g: 255, b: 0 }, Led { r: 0, g: 0, b: 255 });
+    let show = [red, green, blue].iter().fold(PatternBuilder::new(), |builder, color| {
+        builder.then(PatternElement { pattern: PatternId::Solid, color: *color, duration: Microseconds(100_000), ..Default::default() })
+    }).finish();
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, show.clone()).unwrap();
+    engine.play_pattern(0, 0, false).unwrap();
+    // The same show left alone, a frame every 10ms
+    let mut reference = PatternEngine::new();
+    reference.set_pattern(0, show).unwrap();
+    reference.play_pattern(0, 0, false).unwrap();
+    let expected: Vec<_> = (0..40).map(|_| reference.run(Microseconds(10_000))[0]).collect();
+    let at = |ms: usize| expected[ms / 10 - 1];
+
+    for _ in 0..3
+    {
+        engine.run(Microseconds(10_000));
+    }
+    engine.play_override(1, PatternBuilder::new()
+        .then(PatternElement { pattern: PatternId::Solid, color: WHITE, duration: Microseconds(150_000), ..Default::default() })
+        .finish());
+    for ms in (40..=180).step_by(10)
+    {
+        let frame = engine.run(Microseconds(10_000));
+        assert_eq!((frame[0], frame[1]), (WHITE, at(ms)), "{}ms", ms);
+    }
+    // The first led picks up 30ms in, where it was paused, while the rest
+    // carry on 150ms ahead of it
+    for ms in (40..=200).step_by(10)
+    {
+        let frame = engine.run(Microseconds(10_000));
+        assert_eq!((frame[0], frame[1]), (at(ms), at(ms + 150)), "{}ms", ms);
+    }
+}
+
+#[test]
+fn overrides_are_drawn_for_each_led()
+{
+    let mut engine = PatternEngine::new();
+    engine.play_override(!0, PatternBuilder::new()
+        .then(PatternElement { pattern: PatternId::EdgeGradient, color: WHITE, duration: Microseconds(100_000), param: 2, ..Default::default() })
+        .finish());
+    let frame = engine.run(Microseconds(99_999));
+    // Lit like the same element on a layer
+    let (near, far) = (rim_led(85), rim_led(213));
+    assert_eq!(frame[near], WHITE);
+    assert_eq!(frame[far], OFF);
+}
+
+#[test]
+fn blend_modes_combine_known_colors()
+{
+    let base = WideLed { r: 40_000, g: 20_000, b: 0 };
+    let top = WideLed { r: 30_000, g: 65_535, b: 1_000 };
+    assert_eq!(BlendMode::Replace.apply(base, top), top);
+    assert_eq!(BlendMode::Add.apply(base, top), WideLed { r: 65_535, g: 65_535, b: 1_000 });
+    assert_eq!(BlendMode::Multiply.apply(base, top), WideLed { r: 18_310, g: 20_000, b: 0 });
+    assert_eq!(BlendMode::Max.apply(base, top), WideLed { r: 40_000, g: 65_535, b: 1_000 });
+}
+
+#[test]
+fn layers_blend_over_the_base()
+{
+    let solid = |color| PatternBuilder::new()
+        .then(PatternElement { pattern: PatternId::Solid, color, duration: Microseconds(100_000), ..Default::default() })
+        .finish();
+    let (base, top) = (Led { r: 200, g: 100, b: 0 }, Led { r: 100, g: 255, b: 50 });
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, solid(base)).unwrap();
+    engine.set_pattern(1, solid(top)).unwrap();
+    engine.play_pattern(0, 0, false).unwrap();
+    engine.play_pattern(1, 1, false).unwrap();
+    engine.enable_layer(1, true).unwrap();
+    let expected = [
+        (BlendMode::Replace, top),
+        (BlendMode::Add, Led { r: 255, g: 255, b: 50 }),
+        // 200 * 100 / 255, white leaves green alone and black stays black
+        (BlendMode::Multiply, Led { r: 78, g: 100, b: 0 }),
+        (BlendMode::Max, Led { r: 200, g: 255, b: 50 }),
+    ];
+    for (blend, color) in expected
+    {
+        engine.set_layer_blend(1, blend).unwrap();
+        let frame = engine.run(Microseconds(10_000));
+        assert!(frame.iter().all(|led| *led == color), "{:?}", blend);
+    }
+}
+
+#[test]
+fn spread_phase_staggers_neighbouring_leds()
+{
+    // Lit for the first half of the pass, which spreading shares out so each
+    // led is two steps further through it than the one before
+    const STEP: u32 = 10_000;
+    let half = Microseconds(STEP * LED_COUNT as u32);
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, PatternBuilder::new()
+        .then(PatternElement { pattern: PatternId::Solid, color: WHITE, duration: half, ..Default::default() })
+        .then(PatternElement { pattern: PatternId::Solid, color: OFF, duration: half, ..Default::default() })
+        .finish()).unwrap();
+    engine.spread_phase(0, true).unwrap();
+    engine.start();
+    let frames: Vec<_> = (0..4 * LED_COUNT).map(|_| engine.run(Microseconds(STEP))).collect();
+    assert!(frames[0].contains(&WHITE) && frames[0].contains(&OFF));
+    for (now, later) in frames.iter().zip(frames.iter().skip(2))
+    {
+        for led in 0..LED_COUNT - 1
+        {
+            assert_eq!(now[led + 1], later[led]);
+        }
+    }
+}
+
+fn hue(led: Led) -> u8
+{
+    Hsv::from(led).h
+}
+
+// Hue difference from a to b, either way round the wheel
+fn hue_step(a: Led, b: Led) -> i32
+{
+    hue(b).wrapping_sub(hue(a)) as i8 as i32
+}
+
+#[test]
+fn rainbow_turns_across_leds_and_over_time()
+{
+    const STEP: u32 = 10_000;
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, PatternBuilder::new()
+        .then(PatternElement { pattern: PatternId::Rainbow, color: Led { r: 255, g: 0, b: 0 }, duration: Microseconds(100 * STEP), ..Default::default() })
+        .finish()).unwrap();
+    engine.play_pattern(0, 0, true).unwrap();
+    let first = engine.run(Microseconds(STEP));
+    // Spread over the leds, each a share of the wheel further round
+    let per_led = 256 / LED_COUNT as i32;
+    for led in 0..LED_COUNT - 1
+    {
+        let step = hue_step(first[led], first[led + 1]);
+        assert!((step - per_led).abs() <= 2, "led {} stepped {}", led, step);
+    }
+    // A tenth of the way round every tenth of a pass
+    for _ in 0..10
+    {
+        engine.run(Microseconds(STEP));
+    }
+    let later = engine.run(Microseconds(0));
+    for led in 0..LED_COUNT
+    {
+        let step = hue_step(first[led], later[led]);
+        assert!((step - 26).abs() <= 2, "led {} turned {}", led, step);
+    }
+}
+
+#[test]
+fn hue_rotate_takes_the_short_or_long_way_round()
+{
+    let (red, blue) = (Led { r: 255, g: 0, b: 0 }, Led { r: 0, g: 0, b: 255 });
+    // Blue is two thirds of the way round from red
+    assert_eq!(hue_step(red, blue), -85);
+    for (pattern, halfway) in [(PatternId::HueRotate, -43i32), (PatternId::HueRotateLong, 85)]
+    {
+        let mut engine = PatternEngine::new();
+        engine.set_pattern(0, PatternBuilder::new()
+            .then(PatternElement { pattern: PatternId::Solid, color: red, duration: Microseconds(100_000), ..Default::default() })
+            .then(PatternElement { pattern, color: blue, duration: Microseconds(100_000), ..Default::default() })
+            .finish()).unwrap();
+        engine.play_pattern(0, 0, false).unwrap();
+        assert_eq!(engine.run(Microseconds(100_000))[0], red);
+        let mut previous = red;
+        for quarter in 1..=4
+        {
+            let led = engine.run(Microseconds(25_000))[0];
+            // Never doubling back
+            let step = hue_step(previous, led);
+            assert!(step.signum() == halfway.signum() || step == 0, "{} stepped {}", pattern as u8, step);
+            if quarter == 2
+            {
+                assert!((hue_step(red, led) - halfway).abs() <= 2, "{} was at {}", pattern as u8, hue_step(red, led));
+            }
+            previous = led;
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use embedded_time::duration::*;
+use hexcell_api::display::Led;
+use hexcell_core::hexcore_errors::PatternError;
+use hexcell_core::patterns::easing::Easing;
+use hexcell_core::patterns::vm::ProgramBuilder;
+use hexcell_core::patterns::{Pattern, PatternBuilder, PatternElement, PatternEngine, PatternId};
+use hexcell_core::patterns::playback::NORMAL_SPEED;
+
+const RED: Led = Led { r: 255, g: 0, b: 0 };
+const GREEN: Led = Led { r: 0, g: 255, b: 0 };
+const BLUE: Led = Led { r: 0, g: 0, b: 255 };
+
+// Three 100ms solid elements, so the color shows where the cursor is
+fn steps() -> Pattern
+{
+    let solid = |color| PatternElement { pattern: PatternId::Solid, color, duration: Microseconds(100_000), ..Default::default() };
+    PatternBuilder::new().then(solid(RED)).then(solid(GREEN)).then(solid(BLUE)).finish()
+}
+
+fn engine(restart: bool) -> PatternEngine
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, steps()).unwrap();
+    engine.start();
+    engine.set_cursor_to_pattern(0, 0, restart).expect("Invalid cursor setting");
+    engine
+}
+
+fn colors(engine: &mut PatternEngine, step: u32, count: usize) -> std::vec::Vec<Led>
+{
+    (0..count).map(|_| engine.run(Microseconds(step))[0]).collect()
+}
+
+#[test]
+fn reverse_plays_elements_backwards_and_wraps()
+{
+    let mut engine = engine(true);
+    engine.cursor_playback_mut(0, 0).unwrap().reverse = true;
+    // From the start, going back wraps round to the end of the pass
+    assert_eq!(colors(&mut engine, 50_000, 7), [BLUE, BLUE, GREEN, GREEN, RED, RED, BLUE]);
+}
+
+#[test]
+fn ping_pong_turns_around_at_each_end()
+{
+    let mut engine = engine(true);
+    engine.playback_mut().ping_pong = true;
+    // Each end is reached once on the way in and once on the way out
+    assert_eq!(colors(&mut engine, 100_000, 8), [GREEN, BLUE, BLUE, GREEN, RED, RED, GREEN, BLUE]);
+}
+
+#[test]
+fn one_shot_ping_pong_stops_back_at_the_start()
+{
+    let mut engine = engine(false);
+    engine.cursor_playback_mut(0, 0).unwrap().ping_pong = true;
+    assert_eq!(colors(&mut engine, 100_000, 8), [GREEN, BLUE, BLUE, GREEN, RED, RED, RED, RED]);
+}
+
+#[test]
+fn paused_cursors_hold_but_can_be_seeked()
+{
+    let mut engine = engine(true);
+    engine.run(Microseconds(150_000));
+    engine.pause();
+    assert_eq!(colors(&mut engine, 100_000, 3), [GREEN, GREEN, GREEN]);
+    engine.seek_cursor_to_element(0, 0, 2).unwrap();
+    assert_eq!(colors(&mut engine, 100_000, 2), [BLUE, BLUE]);
+    engine.seek(Microseconds(10_000));
+    assert_eq!(engine.run(Microseconds(100_000))[0], RED);
+    engine.resume();
+    // The tick that finishes an element still shows it
+    assert_eq!(colors(&mut engine, 100_000, 2), [RED, GREEN]);
+}
+
+#[test]
+fn speeds_multiply_and_keep_fractions()
+{
+    let mut engine = engine(true);
+    // Cursor at half speed under a global 1.5x is 0.75x
+    engine.cursor_playback_mut(0, 0).unwrap().speed = NORMAL_SPEED / 2;
+    engine.playback_mut().speed = NORMAL_SPEED + NORMAL_SPEED / 2;
+    // 1us ticks only advance by fractions, which must still add up
+    for _ in 0..133_334
+    {
+        engine.run(Microseconds(1));
+    }
+    assert_eq!(engine.run(Microseconds(0))[0], GREEN);
+}
+
+#[test]
+fn seeking_a_program_cursor_is_refused()
+{
+    let mut engine = engine(true);
+    assert!(engine.seek_cursor(0, 0, Microseconds(0)).is_ok());
+    assert!(engine.seek_cursor_to_element(0, 0, 3).is_err());
+    assert!(engine.seek_cursor(0, 99, Microseconds(0)).is_err());
+    assert!(engine.cursor_playback_mut(7, 0).is_err());
+
+    // Programs have no timeline to seek along
+    let program = ProgramBuilder::new().play(PatternId::Solid, 0, Microseconds(100_000), Easing::Linear, 0).finish().unwrap();
+    engine.set_program(0, program).unwrap();
+    engine.set_cursor_to_program(0, 0, true).unwrap();
+    assert!(matches!(engine.seek_cursor(0, 0, Microseconds(50_000)), Err(PatternError::InvalidProgramError)));
+    assert!(matches!(engine.seek_cursor_to_element(0, 0, 0), Err(PatternError::InvalidProgramError)));
+    // Other cursors still seek
+    assert!(engine.seek_cursor(0, 1, Microseconds(50_000)).is_ok());
+}
+
+#[test]
+fn seeking_everything_leaves_finished_and_hidden_cursors_alone()
+{
+    let mut engine = engine(false);
+    assert_eq!(colors(&mut engine, 100_000, 4), [RED, GREEN, BLUE, BLUE]);
+    // The one-shot holds its last color rather than playing again
+    engine.seek(Microseconds(10_000));
+    engine.seek_element(0);
+    assert_eq!(colors(&mut engine, 100_000, 2), [BLUE, BLUE]);
+
+    // A hidden layer keeps its place
+    engine.play_pattern(1, 0, false).unwrap();
+    engine.seek(Microseconds(200_000));
+    engine.seek_element(2);
+    engine.enable_layer(1, true).unwrap();
+    assert_eq!(colors(&mut engine, 50_000, 2), [RED, RED]);
+}
@@ -0,0 +1,177 @@
+use core::cell::RefCell;
+use embedded_time::duration::*;
+use hexcell_api::display::{Led, LED_COUNT};
+use hexcell_api::timer::Timestamp;
+use hexcell_core::hexcore::HexCellCore;
+use hexcell_core::hexcore_errors::PatternError;
+use hexcell_core::patterns::presets::{BuiltinPreset, PresetCommand, PresetParams, PresetRegistry, PresetScope, BUILTIN_PRESET_COUNT, MAX_USER_PRESETS, PRESET_COMMAND_SIZE, USER_PRESET_BASE};
+use hexcell_core::patterns::{CursorEvent, Pattern, PatternBuilder, PatternElement, PatternEngine, PatternId};
+use hexcell_core::ports::HardPort;
+use hexcell_core::scheduler::Scheduler;
+use zerocopy::AsBytes;
+
+const RED: Led = Led { r: 255, g: 0, b: 0 };
+const OFF: Led = Led { r: 0, g: 0, b: 0 };
+const PERIOD: u32 = 1_200_000;
+// Samples per pass
+const STEPS: u32 = 24;
+
+fn params(color: Led, period: u32) -> PresetParams
+{
+    PresetParams { color, period: Microseconds(period) }
+}
+
+// Loads a preset the way the core does, on the base layer of every led
+fn engine(registry: &PresetRegistry, id: u8, params: &PresetParams) -> PatternEngine
+{
+    let preset = registry.build(id, params).unwrap();
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, preset.pattern).unwrap();
+    engine.play_pattern(0, 0, preset.spread).unwrap();
+    engine
+}
+
+// Every frame of one pass
+fn one_pass(engine: &mut PatternEngine) -> std::vec::Vec<[Led; LED_COUNT]>
+{
+    (0..STEPS).map(|_| engine.run(Microseconds(PERIOD / STEPS))).collect()
+}
+
+fn lit(frame: &[Led; LED_COUNT]) -> usize
+{
+    frame.iter().filter(|led| **led != OFF).count()
+}
+
+fn unknown(result: Result<(), PatternError>) -> bool
+{
+    matches!(result, Err(PatternError::UnknownPresetError))
+}
+
+#[test]
+fn every_builtin_builds_and_loops()
+{
+    let registry = PresetRegistry::new();
+    for id in 0..BUILTIN_PRESET_COUNT
+    {
+        assert!(registry.contains(id));
+        let preset = registry.build(id, &params(RED, PERIOD)).unwrap();
+        assert_eq!(preset.pattern.duration().integer(), PERIOD, "preset {}", id);
+        let mut engine = engine(&registry, id, &params(RED, PERIOD));
+        let frames = one_pass(&mut engine);
+        let events: std::vec::Vec<_> = core::iter::from_fn(|| engine.poll_event()).collect();
+        assert!(events.iter().all(|e| e.event != CursorEvent::Finished), "preset {}", id);
+        assert!(events.iter().any(|e| e.cursor == 0 && e.event == CursorEvent::Looped), "preset {}", id);
+        // Off is the only one that stays dark
+        assert_eq!(frames.iter().any(|frame| lit(frame) > 0), id != 0, "preset {}", id);
+    }
+}
+
+#[test]
+fn builtins_follow_their_params()
+{
+    let registry = PresetRegistry::new();
+    // Zero keeps the preset's own timing
+    let solid = registry.build(1, &PresetParams::default()).unwrap();
+    assert_eq!(solid.pattern.duration(), Microseconds(1_000_000u32));
+    let frames = one_pass(&mut engine(&registry, 1, &params(RED, PERIOD)));
+    assert!(frames.iter().all(|frame| frame.iter().all(|led| *led == RED)));
+    // Blink is dark for the first half of each pass and lit for the second, in unison
+    let frames = one_pass(&mut engine(&registry, 2, &params(RED, PERIOD)));
+    assert_eq!(lit(&frames[0]), 0);
+    assert_eq!(lit(&frames[STEPS as usize - 2]), LED_COUNT);
+    // Chase lights a different led at a time
+    let frames = one_pass(&mut engine(&registry, 7, &params(RED, PERIOD)));
+    assert!(frames.iter().all(|frame| lit(frame) <= 2));
+    assert!(!registry.build(1, &params(RED, PERIOD)).unwrap().spread);
+    assert!(registry.build(7, &params(RED, PERIOD)).unwrap().spread);
+}
+
+#[test]
+fn user_presets_are_stretched_and_tinted()
+{
+    let solid = |color, duration| PatternElement { pattern: PatternId::Solid, color, duration: Microseconds(duration), ..Default::default() };
+    let gray = Led { r: 200, g: 200, b: 200 };
+    let pattern = PatternBuilder::new().then(solid(gray, 100_000)).then(solid(OFF, 300_000)).finish();
+    let mut registry = PresetRegistry::new();
+    registry.store(USER_PRESET_BASE, pattern, false).unwrap();
+    assert!(registry.contains(USER_PRESET_BASE));
+    // The first quarter of the pass is lit, in the tint, counting the wrap back to the start
+    let mut engine = engine(&registry, USER_PRESET_BASE, &params(Led { r: 255, g: 128, b: 0 }, PERIOD));
+    let frames = one_pass(&mut engine);
+    let on = frames.iter().filter(|frame| frame[0] != OFF).count();
+    assert_eq!(on, STEPS as usize / 4);
+    assert_eq!(frames[0][0], Led { r: 200, g: 100, b: 0 });
+    // Storing again replaces it
+    registry.store(USER_PRESET_BASE, PatternBuilder::new().then(solid(RED, 10)).finish(), true).unwrap();
+    let preset = registry.build(USER_PRESET_BASE, &PresetParams::default()).unwrap();
+    assert_eq!((preset.pattern.len(), preset.spread), (1, true));
+}
+
+#[test]
+fn out_of_range_presets_are_refused()
+{
+    let mut registry = PresetRegistry::new();
+    for id in [BUILTIN_PRESET_COUNT, USER_PRESET_BASE - 1, USER_PRESET_BASE, 0xFF]
+    {
+        assert!(!registry.contains(id));
+        assert!(unknown(registry.build(id, &PresetParams::default()).map(|_| ())), "preset {}", id);
+    }
+    let pattern = PatternBuilder::new().then(PatternElement { duration: Microseconds(10), ..Default::default() }).finish();
+    // Built-in ids can't be stored over, and stored presets need elements
+    assert!(unknown(registry.store(BUILTIN_PRESET_COUNT, pattern.clone(), false)));
+    assert!(matches!(registry.store(USER_PRESET_BASE, Pattern::new(), false), Err(PatternError::InvalidPatternError)));
+    for slot in 0..MAX_USER_PRESETS as u8
+    {
+        registry.store(USER_PRESET_BASE + slot, pattern.clone(), false).unwrap();
+    }
+    assert!(matches!(registry.store(0xFF, pattern, false), Err(PatternError::PatternCountError)));
+    registry.remove(USER_PRESET_BASE).unwrap();
+    assert!(unknown(registry.remove(USER_PRESET_BASE)));
+}
+
+#[test]
+fn commands_carry_a_preset_and_its_params()
+{
+    let sent = params(Led { r: 1, g: 2, b: 3 }, 0x0A0B_0C0D);
+    let command = PresetCommand::new(7, &sent, PresetScope::Hive, 42);
+    let bytes = command.as_bytes();
+    assert_eq!(bytes, [7, 1, 42, 1, 2, 3, 0x0D, 0x0C, 0x0B, 0x0A]);
+    let received = PresetCommand::decode(bytes).unwrap();
+    assert_eq!((received.id(), received.params(), received.sequence()), (7, sent, 42));
+    assert!(matches!(received.scope(), Ok(PresetScope::Hive)));
+    assert!(matches!(PresetCommand::decode(&bytes[..PRESET_COMMAND_SIZE - 1]), Err(PatternError::TruncatedDataError)));
+    let mut bad_scope = bytes.to_vec();
+    bad_scope[1] = 2;
+    assert!(PresetCommand::decode(&bad_scope).is_err());
+}
+
+#[test]
+fn stale_hive_commands_are_dropped()
+{
+    const BLUE: Led = Led { r: 0, g: 0, b: 255 };
+    let scheduler = RefCell::new(Scheduler::new());
+    let mut core = HexCellCore::new(&scheduler);
+    core.init(Timestamp::ZERO);
+    let mut now = 0;
+    // Hands a hive command to the core, returning whether it was passed on
+    // and what the leds show after
+    let mut receive = |core: &mut HexCellCore, color: Led, sequence: u8| {
+        let command = PresetCommand::new(BuiltinPreset::Solid as u8, &params(color, 0), PresetScope::Hive, sequence);
+        let message = core.preset_message(HardPort::PORT_A, &command);
+        let forwarded = core.handle_preset_command(&message).unwrap().is_some();
+        now += 10_000;
+        core.tick(Timestamp::from_micros(now));
+        (forwarded, core.pattern_buffer()[0])
+    };
+    assert_eq!(receive(&mut core, RED, 5), (true, RED));
+    assert_eq!(receive(&mut core, BLUE, 7), (true, BLUE));
+    // Repeats, and older commands arriving late round a loop
+    assert_eq!(receive(&mut core, BLUE, 7), (false, BLUE));
+    assert_eq!(receive(&mut core, RED, 6), (false, BLUE));
+    assert_eq!(receive(&mut core, RED, 200), (false, BLUE));
+    // Newer across the wrap
+    assert_eq!(receive(&mut core, RED, 130), (true, RED));
+    assert_eq!(receive(&mut core, BLUE, 255), (true, BLUE));
+    assert_eq!(receive(&mut core, RED, 2), (true, RED));
+    assert_eq!(receive(&mut core, BLUE, 254), (false, RED));
+}
@@ -0,0 +1,200 @@
+use embedded_time::duration::*;
+use hexcell_api::display::Led;
+use hexcell_core::hexcore_errors::PatternError;
+use hexcell_core::patterns::easing::Easing;
+use hexcell_core::patterns::vm::{Program, ProgramBuilder, VmAction, VmState, MAX_PROGRAM_SIZE};
+use hexcell_core::patterns::{CursorEvent, PatternBuilder, PatternElement, PatternEngine, PatternId};
+
+const RED: Led = Led { r: 255, g: 0, b: 0 };
+const GREEN: Led = Led { r: 0, g: 255, b: 0 };
+const BLUE: Led = Led { r: 0, g: 0, b: 255 };
+
+fn play(builder: ProgramBuilder, register: u8) -> ProgramBuilder
+{
+    builder.play(PatternId::Solid, register, Microseconds(100_000), Easing::Linear, 0)
+}
+
+// Runs program on the first led only
+fn engine(program: Program, restart: bool) -> PatternEngine
+{
+    let mut engine = PatternEngine::new();
+    engine.set_program(0, program).unwrap();
+    engine.set_cursor_to_program(0, 0, restart).unwrap();
+    engine.start();
+    engine
+}
+
+fn colors(engine: &mut PatternEngine, count: usize) -> std::vec::Vec<Led>
+{
+    (0..count).map(|_| engine.run(Microseconds(100_000))[0]).collect()
+}
+
+// Whether the program's cursor stopped since the last check
+fn finished(engine: &mut PatternEngine) -> bool
+{
+    core::iter::from_fn(|| engine.poll_event()).any(|e| e.cursor == 0 && e.event == CursorEvent::Finished)
+}
+
+fn rejects(code: &[u8]) -> PatternError
+{
+    match Program::new(code)
+    {
+        Ok(_) => panic!("Accepted {:02x?}", code),
+        Err(error) => error
+    }
+}
+
+#[test]
+fn programs_play_repeat_and_halt()
+{
+    let program = play(play(ProgramBuilder::new()
+        .set_color(0, RED)
+        .set_color(1, GREEN)
+        .repeat(2), 0)
+        .end(), 1)
+        .halt()
+        .finish()
+        .unwrap();
+    let mut engine = engine(program, false);
+    assert_eq!(colors(&mut engine, 3), [RED, RED, GREEN]);
+    // Halted, the last color is held
+    assert!(finished(&mut engine));
+    assert_eq!(colors(&mut engine, 2), [GREEN, GREEN]);
+    assert!(!finished(&mut engine));
+    assert!(engine.cursor_fault(0, 0).is_none());
+}
+
+#[test]
+fn halted_programs_restart_when_asked()
+{
+    let program = play(play(ProgramBuilder::new()
+        .set_color(0, RED)
+        .set_color(1, BLUE), 0), 1)
+        .finish()
+        .unwrap();
+    let mut engine = engine(program, true);
+    // Running off the end halts, then starts over
+    assert_eq!(colors(&mut engine, 5), [RED, BLUE, RED, BLUE, RED]);
+    assert!(!finished(&mut engine));
+}
+
+#[test]
+fn jumps_and_branches_move_the_program_counter()
+{
+    let start = ProgramBuilder::new().set_color(0, RED).set_color(1, GREEN);
+    let top = start.position();
+    // Never taken, then always back to the top
+    let builder = play(start, 0).random_branch(0, 0);
+    let program = play(builder, 1).jump(top).finish().unwrap();
+    let mut engine = engine(program, false);
+    assert_eq!(colors(&mut engine, 6), [RED, GREEN, RED, GREEN, RED, GREEN]);
+    assert!(!finished(&mut engine));
+}
+
+#[test]
+fn random_branches_replay_for_a_seed()
+{
+    let program = play(ProgramBuilder::new().random_branch(128, 13), 0).halt().finish().unwrap();
+    let choices = |seed| {
+        let mut vm = VmState::new(seed);
+        (0..32).map(|_| {
+            vm.reset();
+            matches!(vm.step(&program), Ok(VmAction::Halt))
+        }).collect::<std::vec::Vec<bool>>()
+    };
+    assert_eq!(choices(7), choices(7));
+    assert_ne!(choices(7), choices(8));
+    // Roughly half of the branches taken
+    let taken = choices(7).iter().filter(|taken| **taken).count();
+    assert!((8..=24).contains(&taken), "{}", taken);
+}
+
+#[test]
+fn waits_hold_until_their_event()
+{
+    let program = play(play(ProgramBuilder::new()
+        .set_color(0, RED)
+        .set_color(1, GREEN), 0)
+        .wait_event(0b10), 1)
+        .halt()
+        .finish()
+        .unwrap();
+    let mut engine = engine(program, false);
+    assert_eq!(colors(&mut engine, 3), [RED, RED, RED]);
+    // Other events leave it waiting
+    engine.raise_event(0b01);
+    assert_eq!(colors(&mut engine, 1), [RED]);
+    engine.raise_event(0b11);
+    assert_eq!(colors(&mut engine, 1), [GREEN]);
+    assert!(finished(&mut engine));
+}
+
+#[test]
+fn calls_play_a_pattern_then_return()
+{
+    let solid = |color| PatternElement { pattern: PatternId::Solid, color, duration: Microseconds(100_000), ..Default::default() };
+    let program = play(ProgramBuilder::new().call(1).set_color(0, BLUE), 0).halt().finish().unwrap();
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(1, PatternBuilder::new().then(solid(RED)).then(solid(GREEN)).finish()).unwrap();
+    engine.set_program(0, program).unwrap();
+    engine.set_cursor_to_program(0, 0, false).unwrap();
+    engine.start();
+    assert_eq!(colors(&mut engine, 3), [RED, GREEN, BLUE]);
+    assert!(engine.cursor_fault(0, 0).is_none());
+}
+
+#[test]
+fn faults_stop_the_cursor()
+{
+    // A loop that never plays anything
+    let stuck = ProgramBuilder::new().set_color(0, RED).jump(0).finish().unwrap();
+    let mut vm = VmState::new(1);
+    assert!(matches!(vm.step(&stuck), Err(PatternError::ProgramStallError)));
+    let mut engine = engine(stuck, true);
+    engine.run(Microseconds(100_000));
+    assert!(matches!(engine.cursor_fault(0, 0), Some(PatternError::ProgramStallError)));
+    assert!(finished(&mut engine));
+
+    // Calling an empty pattern slot
+    let mut engine = self::engine(ProgramBuilder::new().call(5).finish().unwrap(), true);
+    engine.run(Microseconds(100_000));
+    assert!(matches!(engine.cursor_fault(0, 0), Some(PatternError::InvalidPatternError)));
+
+    // A program that halts straight after restarting has nothing to play
+    let mut engine = self::engine(ProgramBuilder::new().halt().finish().unwrap(), true);
+    engine.run(Microseconds(100_000));
+    assert!(finished(&mut engine));
+    assert!(engine.cursor_fault(0, 0).is_none());
+}
+
+#[test]
+fn malformed_bytecode_is_rejected()
+{
+    // Unknown opcode, and operands cut short
+    assert!(matches!(rejects(&[0x09]), PatternError::InvalidOpcodeError));
+    assert!(matches!(rejects(&[0x01, 0x00, 0x00, 0x10]), PatternError::TruncatedDataError));
+    assert!(matches!(rejects(&[0x05, 0x00]), PatternError::TruncatedDataError));
+    // PLAY with an unknown pattern, an unknown easing or a bad register
+    assert!(matches!(rejects(&[0x01, 0xEE, 0, 0, 0, 1, 0, 0, 0]), PatternError::UnknownPatternIdError));
+    assert!(matches!(rejects(&[0x01, 0, 0, 0, 0, 1, 0, 0xEE, 0]), PatternError::UnknownEasingError));
+    assert!(matches!(rejects(&[0x01, 0, 4, 0, 0, 1, 0, 0, 0]), PatternError::InvalidRegisterError));
+    assert!(matches!(rejects(&[0x02, 4, 1, 2, 3]), PatternError::InvalidRegisterError));
+    // Unbalanced or too deeply nested REPEAT blocks
+    assert!(matches!(rejects(&[0x04]), PatternError::ProgramStackError));
+    assert!(matches!(rejects(&[0x03, 2]), PatternError::ProgramStackError));
+    assert!(matches!(rejects(&[0x03, 2, 0x03, 2, 0x03, 2, 0x03, 2, 0x03, 2, 0x04, 0x04, 0x04, 0x04, 0x04]), PatternError::ProgramStackError));
+    // Jumps into an operand or past the end
+    assert!(matches!(rejects(&[0x02, 0, 1, 2, 3, 0x05, 0x02, 0x00]), PatternError::ProgramBoundsError));
+    assert!(matches!(rejects(&[0x05, 0x04, 0x00]), PatternError::ProgramBoundsError));
+    assert!(matches!(rejects(&[0x06, 0x80, 0x09, 0x00]), PatternError::ProgramBoundsError));
+    // Landing exactly on the end halts
+    assert!(Program::new(&[0x05, 0x03, 0x00]).is_ok());
+    // Too long to hold, whether loaded or assembled
+    assert!(matches!(rejects(&[0x00; MAX_PROGRAM_SIZE + 1]), PatternError::PatternSizeError));
+    let mut builder = ProgramBuilder::new();
+    for _ in 0..MAX_PROGRAM_SIZE
+    {
+        builder = builder.halt();
+    }
+    assert!(builder.halt().finish().is_err());
+}
@@ -0,0 +1,51 @@
+use core::cell::{Cell, RefCell};
+use embedded_time::duration::*;
+use hexcell_api::display::Led;
+use hexcell_api::timer::{ExtendedTimer, Timer32, Timer64};
+use hexcell_core::hexcore::HexCellCore;
+use hexcell_core::patterns::presets::{BuiltinPreset, PresetParams};
+use hexcell_core::scheduler::Scheduler;
+
+struct FakeTimer
+{
+    now: Cell<u32>,
+}
+
+impl Timer32 for FakeTimer
+{
+    fn now(&self) -> Microseconds<u32>
+    {
+        Microseconds(self.now.get())
+    }
+}
+
+#[test]
+fn patterns_keep_time_across_timer_wrap()
+{
+    const RED: Led = Led { r: 255, g: 0, b: 0 };
+    const OFF: Led = Led { r: 0, g: 0, b: 0 };
+    // Starts 100ms before the 32 bit counter wraps
+    let timer = ExtendedTimer::new(FakeTimer { now: Cell::new(u32::MAX - 99_999) });
+    let scheduler = RefCell::new(Scheduler::new());
+    let mut core = HexCellCore::new(&scheduler);
+    core.init(timer.now64());
+    core.select_preset(BuiltinPreset::Blink as u8, &PresetParams { color: RED, period: Microseconds(1_000_000) }).expect("Invalid preset");
+    core.tick(timer.now64());
+
+    // 10ms steps: patterns are aligned to time zero, so the blink is dark for
+    // the first half of every second and lit for the second
+    for step in 0..120
+    {
+        timer.inner().now.set(timer.inner().now.get().wrapping_add(10_000));
+        let now = timer.now64();
+        core.tick(now);
+        // The tick that crosses an element boundary still shows the element it finished
+        if now.micros() % 500_000 < 10_000
+        {
+            continue;
+        }
+        let expected = if now.micros() % 1_000_000 < 500_000 { OFF } else { RED };
+        assert_eq!(core.pattern_buffer()[0], expected, "step {}", step);
+    }
+    assert!(timer.now64().micros() > u32::MAX as u64);
+}
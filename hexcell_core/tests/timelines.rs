@@ -0,0 +1,166 @@
+use embedded_time::duration::*;
+use hexcell_api::display::Led;
+use hexcell_core::hexcore_errors::PatternError;
+use hexcell_core::patterns::timeline::{encode_timeline, Timeline, KEYFRAME_INTERVAL, TIMELINE_FORMAT_VERSION, TIMELINE_HEADER_SIZE};
+use hexcell_core::patterns::{CursorEvent, PatternEngine};
+
+const PERIOD: u32 = 10_000;
+
+fn gray(level: u8) -> Led
+{
+    Led { r: level, g: level, b: level }
+}
+
+fn encode(frames: &[Led]) -> std::vec::Vec<u8>
+{
+    let mut out = [0u8; 1024];
+    let length = encode_timeline(frames, Microseconds(PERIOD), &mut out).unwrap();
+    out[..length].to_vec()
+}
+
+fn timeline(frames: &[Led]) -> Timeline
+{
+    Timeline::new(std::vec::Vec::leak(encode(frames))).unwrap()
+}
+
+// Header for a timeline sampled every PERIOD
+fn header() -> std::vec::Vec<u8>
+{
+    let mut bytes = std::vec![TIMELINE_FORMAT_VERSION];
+    bytes.extend_from_slice(&PERIOD.to_le_bytes());
+    bytes
+}
+
+fn rejects(records: &[u8]) -> PatternError
+{
+    let mut data = header();
+    data.extend_from_slice(records);
+    match Timeline::new(std::vec::Vec::leak(data))
+    {
+        Ok(_) => panic!("Accepted {:02x?}", records),
+        Err(error) => error
+    }
+}
+
+// Plays frames on the first led
+fn engine(frames: &[Led], restart: bool) -> PatternEngine
+{
+    let mut engine = PatternEngine::new();
+    engine.set_timeline(0, timeline(frames)).unwrap();
+    engine.set_cursor_to_timeline(0, 0, restart).unwrap();
+    engine.start();
+    engine
+}
+
+// Holds, ramps, jumps too big to ramp, and steps that change every frame
+fn recording() -> std::vec::Vec<Led>
+{
+    let mut frames = std::vec![gray(10); 70];
+    frames.extend((1..=20).map(|step| Led { r: 10 + 3 * step, g: 10 - step / 2, b: 10 }));
+    frames.push(Led { r: 250, g: 0, b: 40 });
+    frames.extend((0..30u8).map(|step| Led { r: 250 - step * (step % 3), g: step, b: 40 }));
+    frames
+}
+
+#[test]
+fn runs_and_ramps_encode_compactly()
+{
+    let mut expected = header();
+    // A key, then the same color for two more frames
+    expected.extend_from_slice(&[0x80, 1, 2, 3, 0x01, 0xFF]);
+    assert_eq!(encode(&[Led { r: 1, g: 2, b: 3 }; 3]), expected);
+
+    let mut expected = header();
+    // +4 red for three frames, -1 blue for two
+    expected.extend_from_slice(&[0x80, 0, 9, 9, 0x42, 0x00, 0x20, 0x41, 0x3E, 0x00, 0xFF]);
+    let frames = [0, 4, 8, 12].map(|r| Led { r, g: 9, b: 9 });
+    let frames = [&frames[..], &[Led { r: 12, g: 9, b: 8 }, Led { r: 12, g: 9, b: 7 }]].concat();
+    assert_eq!(encode(&frames), expected);
+}
+
+#[test]
+fn keyframes_are_forced_on_long_recordings()
+{
+    let frames = std::vec![gray(7); 600];
+    let data = encode(&frames);
+    let keys = data[TIMELINE_HEADER_SIZE..].iter().filter(|op| **op == 0x80).count();
+    assert_eq!(keys, 600_usize.div_ceil(KEYFRAME_INTERVAL as usize));
+    let timeline = Timeline::new(std::vec::Vec::leak(data)).unwrap();
+    assert_eq!(timeline.frames(), 600);
+    assert_eq!(timeline.duration(), Microseconds(600 * PERIOD as u64));
+}
+
+#[test]
+fn recordings_play_back_frame_for_frame()
+{
+    let frames = recording();
+    let mut engine = engine(&frames, true);
+    assert_eq!(engine.run(Microseconds(0))[0], frames[0]);
+    // Twice round, the second pass starting over from the first frame
+    for pass in 0..2
+    {
+        for (index, frame) in frames.iter().enumerate().skip(1)
+        {
+            assert_eq!(engine.run(Microseconds(PERIOD))[0], *frame, "pass {} frame {}", pass, index);
+        }
+        assert_eq!(engine.run(Microseconds(PERIOD))[0], frames[0]);
+    }
+    // Partial frames hold the color, big steps skip through runs
+    assert_eq!(engine.run(Microseconds(PERIOD / 2))[0], frames[0]);
+    assert_eq!(engine.run(Microseconds(80 * PERIOD))[0], frames[80]);
+    assert_eq!(engine.run(Microseconds(PERIOD / 2))[0], frames[81]);
+}
+
+#[test]
+fn seeking_lands_on_the_right_frame()
+{
+    let frames = recording();
+    let mut engine = engine(&frames, true);
+    for index in [0, 5, 69, 70, 75, 90, 91, 100, frames.len() - 1]
+    {
+        engine.seek_cursor(0, 0, Microseconds(index as u32 * PERIOD + PERIOD / 2)).unwrap();
+        assert_eq!(engine.run(Microseconds(0))[0], frames[index], "frame {}", index);
+        engine.seek_cursor_to_element(0, 0, index).unwrap();
+        assert_eq!(engine.run(Microseconds(0))[0], frames[index], "element {}", index);
+    }
+    // Wrapped to one pass
+    engine.seek_cursor(0, 0, Microseconds((frames.len() + 3) as u32 * PERIOD)).unwrap();
+    assert_eq!(engine.run(Microseconds(0))[0], frames[3]);
+    assert!(engine.seek_cursor_to_element(0, 0, frames.len()).is_err());
+    // Playing on from a seek carries on through the run it landed in
+    engine.seek_cursor(0, 0, Microseconds(72 * PERIOD)).unwrap();
+    assert_eq!(engine.run(Microseconds(3 * PERIOD))[0], frames[75]);
+}
+
+#[test]
+fn one_shot_recordings_finish_on_their_last_frame()
+{
+    let frames = [gray(1), gray(2), gray(3)];
+    let mut engine = engine(&frames, false);
+    assert_eq!(engine.run(Microseconds(2 * PERIOD))[0], gray(3));
+    assert!(engine.poll_event().is_none());
+    assert_eq!(engine.run(Microseconds(PERIOD))[0], gray(3));
+    assert_eq!(engine.poll_event().map(|e| (e.cursor, e.event)), Some((0, CursorEvent::Finished)));
+    assert_eq!(engine.run(Microseconds(PERIOD))[0], gray(3));
+}
+
+#[test]
+fn malformed_timelines_are_rejected()
+{
+    assert!(matches!(Timeline::new(&[TIMELINE_FORMAT_VERSION, 0x10]), Err(PatternError::TruncatedDataError)));
+    assert!(matches!(Timeline::new(&[TIMELINE_FORMAT_VERSION + 1, 0x10, 0, 0, 0, 0x80, 0, 0, 0]), Err(PatternError::UnsupportedVersionError)));
+    assert!(matches!(Timeline::new(&[TIMELINE_FORMAT_VERSION, 0, 0, 0, 0, 0x80, 0, 0, 0]), Err(PatternError::InvalidPatternError)));
+    // No frames at all, or not starting on a key
+    assert!(matches!(rejects(&[]), PatternError::InvalidPatternError));
+    assert!(matches!(rejects(&[0xFF]), PatternError::InvalidPatternError));
+    assert!(matches!(rejects(&[0x03, 0x80, 1, 2, 3]), PatternError::InvalidPatternError));
+    // Unknown records and records cut short
+    assert!(matches!(rejects(&[0x80, 1, 2, 3, 0xC0]), PatternError::InvalidOpcodeError));
+    assert!(matches!(rejects(&[0x80, 1, 2]), PatternError::TruncatedDataError));
+    assert!(matches!(rejects(&[0x80, 1, 2, 3, 0x41, 0x00]), PatternError::TruncatedDataError));
+    // Nothing to encode, or nowhere to put it
+    let mut out = [0u8; 8];
+    assert!(matches!(encode_timeline(&[], Microseconds(PERIOD), &mut out), Err(PatternError::InvalidPatternError)));
+    assert!(matches!(encode_timeline(&[gray(1)], Microseconds(0), &mut out), Err(PatternError::InvalidPatternError)));
+    assert!(matches!(encode_timeline(&[gray(1), gray(200)], Microseconds(PERIOD), &mut out), Err(PatternError::PatternSizeError)));
+}
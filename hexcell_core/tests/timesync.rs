@@ -0,0 +1,205 @@
+use embedded_time::duration::*;
+use hexcell_api::timer::Timestamp;
+use hexcell_core::networking::timesync::SOURCE_TIMEOUT;
+use hexcell_core::networking::TimeSync;
+
+// One way link latency
+const LINK_DELAY: u64 = 200;
+
+// A cell whose timer runs fast or slow by drift_ppm
+struct DriftingClock
+{
+    drift_ppm: i64,
+    offset: u64,
+}
+
+impl DriftingClock
+{
+    fn local(&self, now: u64) -> Timestamp
+    {
+        let drifted = now as i64 + (now as i64 * self.drift_ppm) / 1_000_000;
+        Timestamp::from_micros(self.offset).offset(drifted)
+    }
+}
+
+// child asks parent for the time over a symmetric link
+fn exchange(child: &mut TimeSync, child_clock: &DriftingClock, parent: &TimeSync, parent_clock: &DriftingClock, now: u64)
+{
+    exchange_on(0, child, child_clock, parent, parent_clock, now);
+}
+
+// As exchange, with the reply arriving on the child's port
+fn exchange_on(port: u8, child: &mut TimeSync, child_clock: &DriftingClock, parent: &TimeSync, parent_clock: &DriftingClock, now: u64)
+{
+    let request = child.request(child_clock.local(now));
+    let arrival = parent_clock.local(now + LINK_DELAY);
+    if let Some(response) = parent.respond(&request, arrival, arrival)
+    {
+        child.complete(&response, port, child_clock.local(now + 2 * LINK_DELAY)).expect("Reply rejected");
+    }
+}
+
+fn skew(a: Timestamp, b: Timestamp) -> i64
+{
+    a.micros() as i64 - b.micros() as i64
+}
+
+#[test]
+fn unsynced_cells_stay_quiet()
+{
+    let mut a = TimeSync::new();
+    let b = TimeSync::new();
+    let request = a.request(Timestamp::ZERO);
+    assert!(b.respond(&request, Timestamp::from_micros(10), Timestamp::from_micros(10)).is_none());
+    assert!(!a.is_synced());
+}
+
+#[test]
+fn drifting_chain_stays_locked()
+{
+    let clocks = [
+        DriftingClock { drift_ppm: 0, offset: 0 },
+        DriftingClock { drift_ppm: 500, offset: 1_234_567 },
+        // Booted days before the root
+        DriftingClock { drift_ppm: -800, offset: 400_000_000_000 },
+    ];
+    let mut root = TimeSync::new();
+    root.make_root();
+    let mut middle = TimeSync::new();
+    let mut edge = TimeSync::new();
+
+    // The edge cell can only hear the root through the middle one
+    exchange(&mut edge, &clocks[2], &middle, &clocks[1], 0);
+    assert!(!edge.is_synced());
+
+    // Resync every second for ten minutes
+    for second in 0..600u64
+    {
+        let now = second * 1_000_000;
+        exchange(&mut middle, &clocks[1], &root, &clocks[0], now);
+        exchange(&mut edge, &clocks[2], &middle, &clocks[1], now + 10_000);
+
+        // Just before the next sync, drift is at its worst
+        let check = now + 999_000;
+        let reference = root.network_time(clocks[0].local(check));
+        assert!(skew(middle.network_time(clocks[1].local(check)), reference).abs() < 1_000, "second {}", second);
+        assert!(skew(edge.network_time(clocks[2].local(check)), reference).abs() < 2_000, "second {}", second);
+    }
+    assert_eq!(middle.hops(), 1);
+    assert_eq!(edge.hops(), 2);
+    assert_eq!(edge.delay(), Microseconds(2 * LINK_DELAY as u32));
+}
+
+#[test]
+fn small_corrections_do_not_resync()
+{
+    let root_clock = DriftingClock { drift_ppm: 0, offset: 0 };
+    let cell_clock = DriftingClock { drift_ppm: 500, offset: 7_000_000 };
+    let mut root = TimeSync::new();
+    root.make_root();
+    let mut cell = TimeSync::new();
+    // Joining is always a step
+    exchange(&mut cell, &cell_clock, &root, &root_clock, 0);
+    assert!(cell.take_resynced());
+    assert!(!cell.take_resynced());
+    // Drift corrections of half a millisecond a second are not
+    for second in 1..10u64
+    {
+        exchange(&mut cell, &cell_clock, &root, &root_clock, second * 1_000_000);
+        assert!(!cell.take_resynced(), "second {}", second);
+    }
+    // The root's clock jumping is
+    let jumped = DriftingClock { drift_ppm: 0, offset: 20_000 };
+    exchange(&mut cell, &cell_clock, &root, &jumped, 10_000_000);
+    assert!(cell.take_resynced());
+}
+
+#[test]
+fn cells_follow_their_source_as_it_moves_away()
+{
+    let clocks = [
+        DriftingClock { drift_ppm: 0, offset: 0 },
+        DriftingClock { drift_ppm: 300, offset: 55_000 },
+        DriftingClock { drift_ppm: -300, offset: 9_000_000 },
+        DriftingClock { drift_ppm: 100, offset: 123 },
+    ];
+    let mut root = TimeSync::new();
+    root.make_root();
+    // A chain hanging off the root, ending three hops out
+    let mut chain = [TimeSync::new(); 3];
+    let mut parent = root;
+    for link in chain.iter_mut()
+    {
+        exchange(link, &clocks[1], &parent, &clocks[0], 0);
+        parent = *link;
+    }
+    let far = chain[2];
+    assert_eq!(far.hops(), 3);
+    // source hears the root directly and cell hears source
+    let mut source = TimeSync::new();
+    let mut cell = TimeSync::new();
+    exchange_on(1, &mut source, &clocks[2], &root, &clocks[0], 0);
+    exchange_on(1, &mut cell, &clocks[3], &source, &clocks[2], 1_000);
+    assert_eq!((source.hops(), cell.hops()), (1, 2));
+
+    // A neighbor further out is ignored while the source keeps answering
+    exchange_on(2, &mut source, &clocks[2], &far, &clocks[1], 1_000_000);
+    assert_eq!(source.hops(), 1);
+    // Once the root goes quiet, source falls back to the far neighbor
+    let later = SOURCE_TIMEOUT + 2_000_000;
+    exchange_on(2, &mut source, &clocks[2], &far, &clocks[1], later);
+    assert_eq!(source.hops(), 4);
+    // and cell keeps following source, though it is now further out
+    exchange_on(1, &mut cell, &clocks[3], &source, &clocks[2], later + 1_000);
+    assert_eq!(cell.hops(), 5);
+    let reference = root.network_time(clocks[0].local(later + 2_000));
+    assert!(skew(cell.network_time(clocks[3].local(later + 2_000)), reference).abs() < 10_000);
+}
+
+#[test]
+fn drifting_hive_converges()
+{
+    // A ring of six cells around the root, only two of which touch it
+    const CELLS: usize = 7;
+    let clocks: std::vec::Vec<DriftingClock> = (0..CELLS as i64)
+        .map(|index| DriftingClock { drift_ppm: (index * 379 % 1_000) - 500, offset: (index as u64) * 987_654_321 })
+        .collect();
+    let mut links = std::vec::Vec::new();
+    links.push((0, 1));
+    links.push((0, 2));
+    for index in 1..CELLS
+    {
+        links.push((index, index % (CELLS - 1) + 1));
+    }
+    let mut cells = [TimeSync::new(); CELLS];
+    cells[0].make_root();
+
+    let spread = |cells: &[TimeSync], now: u64| {
+        let times: std::vec::Vec<i64> = cells.iter().zip(clocks.iter())
+            .map(|(cell, clock)| cell.network_time(clock.local(now)).micros() as i64)
+            .collect();
+        times.iter().max().unwrap() - times.iter().min().unwrap()
+    };
+    assert!(spread(&cells, 0) > 1_000_000_000);
+
+    for second in 0..60u64
+    {
+        let now = second * 1_000_000;
+        for (a, b) in links.iter().copied()
+        {
+            // Each side names its port after the neighbor
+            let (answer, clock) = (cells[b], &clocks[b]);
+            exchange_on(b as u8, &mut cells[a], &clocks[a], &answer, clock, now);
+            let (answer, clock) = (cells[a], &clocks[a]);
+            exchange_on(a as u8, &mut cells[b], &clocks[b], &answer, clock, now);
+        }
+        // Cells furthest out need a couple of rounds to hear the root
+        if second > 1
+        {
+            assert!(spread(&cells, now + 999_000) < 2_000, "second {}", second);
+        }
+    }
+    assert!(cells.iter().all(|cell| cell.is_synced()));
+    let hops: std::vec::Vec<u8> = cells.iter().map(|cell| cell.hops()).collect();
+    assert_eq!(hops, [0, 1, 1, 2, 3, 3, 2]);
+}
@@ -0,0 +1,81 @@
+use embedded_time::duration::*;
+use hexcell_api::display::Led;
+use hexcell_core::hexcore_errors::PatternError;
+use hexcell_core::patterns::{Pattern, PatternBuilder, PatternElement, PatternEngine, PatternId, MAX_PATTERN_COUNT};
+use hexcell_core::patterns::easing::Easing;
+
+const RED: Led = Led { r: 255, g: 0, b: 0 };
+const BLUE: Led = Led { r: 0, g: 0, b: 255 };
+
+fn solids(colors: &[Led]) -> Pattern
+{
+    colors.iter().fold(PatternBuilder::new(), |builder, color| {
+        builder.then(PatternElement { pattern: PatternId::Solid, color: *color, duration: Microseconds(100_000), ..Default::default() })
+    }).finish()
+}
+
+#[test]
+fn crossfade_blends_into_the_new_pattern()
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, solids(&[RED])).unwrap();
+    engine.play_pattern(0, 0, false).unwrap();
+    assert_eq!(engine.run(Microseconds(10_000))[0], RED);
+
+    engine.crossfade_pattern(0, solids(&[BLUE]), Microseconds(100_000), Easing::Linear).unwrap();
+    assert!(engine.is_crossfading());
+    let halfway = engine.run(Microseconds(50_000))[0];
+    // Rounded from the wide frame, so within a step of the 8 bit blend
+    let expected = RED.blend_linear(BLUE, 128);
+    assert!(halfway.r.abs_diff(expected.r) <= 1 && halfway.g == 0 && halfway.b.abs_diff(expected.b) <= 1, "{:?}", halfway);
+    assert_eq!(engine.run(Microseconds(50_000))[0], BLUE);
+    assert!(!engine.is_crossfading());
+}
+
+#[test]
+fn shorter_pattern_restarts_cursors_in_range()
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, solids(&[RED, RED, RED, RED])).unwrap();
+    engine.play_pattern(0, 0, false).unwrap();
+    // Well into the third element
+    for _ in 0..5
+    {
+        engine.run(Microseconds(50_000));
+    }
+    engine.set_pattern(0, solids(&[BLUE])).unwrap();
+    assert_eq!(engine.run(Microseconds(10_000))[0], BLUE);
+    assert_eq!(engine.run(Microseconds(100_000))[0], BLUE);
+}
+
+#[test]
+fn crossfades_start_from_the_show_beneath_overrides()
+{
+    let mut engine = PatternEngine::new();
+    engine.set_pattern(0, solids(&[RED])).unwrap();
+    engine.play_pattern(0, 0, false).unwrap();
+    assert_eq!(engine.run(Microseconds(10_000))[0], RED);
+    let blink = PatternBuilder::new()
+        .then(PatternElement { pattern: PatternId::Solid, color: Led { r: 255, g: 255, b: 255 }, duration: Microseconds(50_000), ..Default::default() })
+        .finish();
+    engine.play_override(1, blink);
+    assert_ne!(engine.run(Microseconds(10_000))[0], RED);
+
+    engine.crossfade_pattern(0, solids(&[BLUE]), Microseconds(100_000), Easing::Linear).unwrap();
+    // Once the blink is over, the first led fades from red like the rest
+    engine.run(Microseconds(50_000));
+    engine.run(Microseconds(10_000));
+    let frame = engine.get_wide_output_buffer();
+    assert_eq!(frame[0], frame[1]);
+    assert_eq!(engine.run(Microseconds(40_000))[0], BLUE);
+}
+
+#[test]
+fn patterns_past_the_last_slot_are_refused()
+{
+    let mut engine = PatternEngine::new();
+    assert!(matches!(engine.set_pattern(MAX_PATTERN_COUNT, solids(&[RED])), Err(PatternError::PatternCountError)));
+    assert!(matches!(engine.crossfade_pattern(MAX_PATTERN_COUNT, solids(&[RED]), Microseconds(100_000), Easing::Linear), Err(PatternError::PatternCountError)));
+    // Refused before anything started fading
+    assert!(!engine.is_crossfading());
+}
@@ -3,16 +3,18 @@ use embedded_error_chain::prelude::*;
 use embedded_time::duration::*;
 use embedded_time::{Clock, Instant};
 use embedded_time::clock::Error as ClkErr;
-use hexcell_core::patterns::{Pattern, PatternElement, PatternBuilder, PatternId};
+use hexcell_core::patterns::dsl;
+use hexcell_core::patterns::presets::{PresetParams, USER_PRESET_BASE};
 use std::time as stdtime;
 use std::thread;
 
 extern crate hexcell_api;
 use hexcell_api::hexcell::HexCell;
-use hexcell_api::display::{Led, LedBuffer, Display, LED_COUNT};
+use hexcell_api::display::{Display, PixelFormat, PixelFrame, LED_COUNT};
 use hexcell_api::messaging::Message;
 use hexcell_api::hexapi_errors::{PhyError, NetworkError};
-use hexcell_api::logging::log;
+use hexcell_api::logging::{log, LogLevel};
+use hexcell_api::timer::Timestamp;
 use piston::RenderArgs;
 
 extern crate hexcell_core;
@@ -27,6 +29,21 @@ use spmc::{Receiver, Sender};
 
 use crate::renderer::Renderer;
 
+// Every simulated cell starts out playing this
+const DEFAULT_SHOW: &str = "
+  blink #ff0000 1s; blink #00ff00 1s; blink #0000ff 1s
+  fade #0000ff 1s; fade #000000 2.5s   // blue in, out
+  fade #00ff00 1s; fade #000000 2.5s   // green in, out
+  fade #ff0000 1s; fade #000000 2.5s   // red in, out
+  fire #ff6010 6s param=12; twinkle #ffffff 4s; sparkle #80c0ff 3s
+  sweep #00ffc0 2s; rotate #ff00ff 3s param=2; edge #ffff00 2s param=1
+";
+
+// How often neighbors exchange TIMESYNC messages
+const TIME_SYNC_PERIOD_US: u64 = 1_000_000;
+// One way latency of a simulated link
+const LINK_DELAY_US: u64 = 150;
+
 #[derive(Clone, Copy)]
 pub enum SimError {
   ExistingDeviceAtCoordinate,
@@ -70,8 +87,12 @@ pub struct HexCellSim
   pub ports: [HexCellPort; HardPort::PORT_COUNT as usize],
   pub connected_flags: u8,
   pub address: u32,
+  pub uid: u32,
   pub message_queue: VecDeque<Message>,
   pub core: HexCellCore,
+  // Deliberate clock error, so time sync has something to correct
+  pub clock_drift_ppm: i32,
+  pub clock_offset_us: u64,
 }
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -86,20 +107,24 @@ pub struct HexCellNetwork
   connection_map: HashMap<Coordinate, HashSet<Coordinate>>, // Coordinate to coordinate
   device_map: HashMap<Coordinate, RefCell<HexCellSim>>, // Coordinate to device model
   clk: HexSimClock,
-  last_tick: Instant<HexSimClock>
+  last_tick: Instant<HexSimClock>,
+  time_root: Option<Coordinate>,
+  last_time_sync: u64,
+  // Shared by every cell, as if the hive ran off one supply
+  power_budget_ma: Option<u32>,
+  // Handed out in creation order, so runs are reproducible
+  next_uid: u32,
 }
 
 impl HexCell for HexCellSim
 {
-  // Writes buffer to leds (via SPI or other interface)
-  fn update_display(&mut self, buffer: &LedBuffer)
+  // Writes a frame, already in the parts' pixel format, to leds (via SPI or other interface)
+  fn update_display(&mut self, frame: &PixelFrame)
   {
-    // Copy display data into render buffer
-    for item in buffer.into_iter().enumerate()
+    // Copy what each part would show into the render buffer
+    for led in 0..LED_COUNT
     {
-      let (led, color): (usize, &Led) = item;
-      self.display.set_led(led, *color);
-    
+      self.display.set_led(led, frame.led(led));
     }
   }
 
@@ -118,11 +143,13 @@ impl HexCell for HexCellSim
   fn port_connect_handler(&mut self, port: u8)
   {
     self.connected_flags |= 1 << port;
+    self.core.port_connected(port);
   }
 
   fn port_disconnect_handler(&mut self, port: u8)
   {
     self.connected_flags &= !(1 << port);
+    self.core.port_disconnected(port);
   }
 
   fn set_address(&mut self, address: u32) -> i16
@@ -136,6 +163,11 @@ impl HexCell for HexCellSim
     self.address
   }
 
+  fn get_uid(&self) -> u32
+  {
+    self.uid
+  }
+
   fn get_message(&mut self) -> Option<Message>
   {
     return self.message_queue.pop_front()
@@ -160,11 +192,12 @@ impl HexCell for HexCellSim
     Ok(())
   }
 
-  fn update(&mut self, now: Microseconds<u32>)
+  fn update(&mut self, now: Timestamp)
   {
       self.core.tick(now);
-      let leds = self.core.pattern_buffer();
-      self.update_display(&leds);
+      let leds = self.display.driven_wide(&self.core.pattern_frame());
+      let frame = self.display.pack(&leds);
+      self.update_display(&frame);
   }
 }
 
@@ -172,72 +205,41 @@ impl HexCellSim
 {
   pub fn new() -> HexCellSim
   {
+    let mut display = Display::new();
+    // Same parts as the hardware, so frames take the same trip
+    display.set_pixel_format(PixelFormat::WS2812);
     HexCellSim {
-      display: Display::new(),
+      display,
       ports: array_init::array_init(|_| { HexCellPort { tx: None, rx: None}}), //HardPort::VP_COUNT as usize],
       connected_flags: 0,
       address: 0,
+      uid: 0,
       message_queue: VecDeque::new(),
       core: HexCellCore::new(),
+      clock_drift_ppm: 0,
+      clock_offset_us: 0,
     }
   }
 
+  // What this cell's own (drifting) timer reads at simulation time now
+  pub fn local_time(&self, now_us: u64) -> Timestamp
+  {
+    let drift = (now_us as i64 * self.clock_drift_ppm as i64) / 1_000_000;
+    Timestamp::from_micros(self.clock_offset_us).offset(now_us as i64 + drift)
+  }
+
+  // Stands in for the id burned into a real part
+  pub fn set_uid(&mut self, uid: u32)
+  {
+    self.uid = uid;
+    self.core.set_uid(uid);
+  }
+
   pub fn default_init(&mut self)
   {
-    let chain = PatternBuilder::new()
-    .then(PatternElement { // Solid
-      pattern: PatternId::Blink,
-      color: Led {r: 255, g: 0, b: 0},
-      duration: Microseconds(1_000_000)
-    })
-    .then(PatternElement { // Solid
-      pattern: PatternId::Blink,
-      color: Led {r: 0, g: 255, b: 0},
-      duration: Microseconds(1_000_000)
-    })
-    .then(PatternElement { // Solid
-      pattern: PatternId::Blink,
-      color: Led {r: 0, g: 0, b: 255},
-      duration: Microseconds(1_000_000)
-    })
-    .then(PatternElement { // Blue in
-      pattern: PatternId::Fade,
-      color: Led {r: 0, g: 0, b: 255},
-      duration: Microseconds(1_000_000)
-    })
-    .then(PatternElement { // Fade OUT
-      pattern: PatternId::Fade,
-      color: Led {r: 0, g: 0, b: 0},
-      duration: Microseconds(2_500_000)
-    })
-    .then(PatternElement { // Green in
-      pattern: PatternId::Fade,
-      color: Led {r: 0, g: 255, b: 0},
-      duration: Microseconds(1_000_000)
-    })
-    .then(PatternElement { // Fade OUT
-      pattern: PatternId::Fade,
-      color: Led {r: 0, g: 0, b: 0},
-      duration: Microseconds(2_500_000)
-    })
-    .then(PatternElement { // Red in
-      pattern: PatternId::Fade,
-      color: Led {r: 255, g: 0, b: 0},
-      duration: Microseconds(1_000_000)
-    })
-    .then(PatternElement { // Fade OUT
-      pattern: PatternId::Fade,
-      color: Led {r: 0, g: 0, b: 0},
-      duration: Microseconds(2_500_000)
-    })
-    .finish();
-    self.core.pattern_engine.start();
-    for i in 0..LED_COUNT
-    {
-      self.core.pattern_engine.set_pattern(i, chain.clone());
-      self.core.pattern_engine.set_cursor_to_pattern(i, 0, true).expect("Invalid cursor setting");
-    }
-     
+    let chain = dsl::parse(DEFAULT_SHOW).expect("Invalid default show");
+    self.core.presets_mut().store(USER_PRESET_BASE, chain, false).expect("Invalid preset");
+    self.core.select_preset(USER_PRESET_BASE, &PresetParams::default()).expect("Invalid preset");
   }
 }
 
@@ -293,6 +295,96 @@ impl HexCellNetwork
       device_map: HashMap::new(),
       clk: clk,
       last_tick: now,
+      time_root: None,
+      last_time_sync: 0,
+      power_budget_ma: None,
+      next_uid: 1,
+    }
+  }
+
+  pub fn set_power_budget(&mut self, budget_ma: Option<u32>)
+  {
+    self.power_budget_ma = budget_ma;
+    self.share_power();
+  }
+
+  // Splits the hive budget evenly, again whenever cells come or go
+  fn share_power(&self)
+  {
+    let cells = self.device_map.len();
+    for dev in self.device_map.values()
+    {
+      dev.borrow_mut().display.set_hive_power_budget(self.power_budget_ma, cells);
+    }
+  }
+
+  pub fn set_clock_drift(&mut self, coord: Coordinate, drift_ppm: i32, offset_us: u64) -> Result<(), SimError>
+  {
+    match self.get_device(coord)
+    {
+      Some(mut dev) => {
+        dev.clock_drift_ppm = drift_ppm;
+        dev.clock_offset_us = offset_us;
+        Ok(())
+      },
+      None => Err(SimError::UnknownDevice)
+    }
+  }
+
+  pub fn set_time_root(&mut self, coord: Coordinate) -> Result<(), SimError>
+  {
+    match self.get_device(coord)
+    {
+      Some(mut dev) => dev.core.make_time_root(),
+      None => return Err(SimError::UnknownDevice)
+    }
+    self.time_root = Some(coord);
+    Ok(())
+  }
+
+  // One TIMESYNC round trip, from asks to, over a link with LINK_DELAY_US latency each way
+  fn exchange_time(&self, from: Coordinate, to: Coordinate, now_us: u64)
+  {
+    let (from_port, _) = match HexCellNetwork::coordinates_to_ports(from, to)
+    {
+      Ok(ports) => ports,
+      Err(_) => return
+    };
+    if let (Some(mut source), Some(mut dest)) = (self.get_device(from), self.get_device(to))
+    {
+      let query = source.core.time_sync_query(from_port, source.local_time(now_us));
+      let arrival = dest.local_time(now_us + LINK_DELAY_US);
+      if let Ok(Some(reply)) = dest.core.handle_time_sync(&query, arrival)
+      {
+        let returned = source.local_time(now_us + 2 * LINK_DELAY_US);
+        if let Err(error) = source.core.handle_time_sync(&reply, returned)
+        {
+          log(LogLevel::WARN, &format!("[timesync] {:?} rejected reply: {:?}", from, error));
+        }
+      }
+    }
+  }
+
+  // Spreads the root's clock over every link, then reports how far apart cells are
+  fn sync_time(&mut self, now_us: u64)
+  {
+    if self.time_root.is_none() || now_us - self.last_time_sync < TIME_SYNC_PERIOD_US
+    {
+      return;
+    }
+    self.last_time_sync = now_us;
+    for (a, b) in self.unique_connections()
+    {
+      self.exchange_time(a, b, now_us);
+      self.exchange_time(b, a, now_us);
+    }
+
+    let times: Vec<i64> = self.device_map.values()
+      .map(|dev| { let dev = dev.borrow(); dev.core.network_time(dev.local_time(now_us)).micros() as i64 })
+      .collect();
+    if let (Some(min), Some(max)) = (times.iter().min(), times.iter().max())
+    {
+      log(LogLevel::DEBUG, &format!("[timesync] network time skew across hive: {}us", max - min));
     }
   }
 
@@ -425,8 +517,11 @@ impl HexCellNetwork
       Some(_) => Err(SimError::ExistingDeviceAtCoordinate),
       None => {
         let dev = RefCell::new(HexCellSim::new());
+        dev.borrow_mut().set_uid(self.next_uid);
+        self.next_uid += 1;
         dev.borrow_mut().default_init();
         self.device_map.insert(coord, dev);
+        self.share_power();
         Ok(())
       }
     }
@@ -569,10 +664,12 @@ impl HexCellNetwork
   pub fn update(&mut self)
   {
     // Move data across connections
-    let now = self.clk.try_now().unwrap().duration_since_epoch().try_into().unwrap();
+    let now_us: u64 = self.clk.try_now().unwrap().duration_since_epoch().integer();
+    self.sync_time(now_us);
     for (_coord, dev) in self.device_map.iter()
     {
-      dev.borrow_mut().update(now);
+      let local = dev.borrow().local_time(now_us);
+      dev.borrow_mut().update(local);
     }
     self.last_tick = self.clk.try_now().unwrap();
     thread::yield_now();
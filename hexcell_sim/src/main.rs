@@ -34,6 +34,15 @@ fn main() {
   net.new_device(Coordinate { x: 0, y: 1});
   net.new_device(Coordinate { x: 1, y: 1});
   net.enable_connection(Coordinate { x: 0, y: 0 }, Coordinate { x: 0, y: 1 });
+  net.enable_connection(Coordinate { x: 0, y: 0 }, Coordinate { x: 1, y: 1 });
+
+  // Deliberately bad clocks, time sync should hold the hive together regardless
+  net.set_time_root(Coordinate { x: 0, y: 0 });
+  net.set_clock_drift(Coordinate { x: 0, y: 1 }, 2_000, 1_234_567);
+  net.set_clock_drift(Coordinate { x: 1, y: 1 }, -3_000, 5_000_000_000);
+
+  // Less than three cells at full white draw, so bright frames get dimmed
+  net.set_power_budget(Some(300));
 
   // Create a new game and run it.
   let mut app = Renderer::new(opengl, Coordinate { x: 128, y: 128 });
@@ -9,25 +9,10 @@ use opengl_graphics::{GlGraphics, GlyphCache, Filter, TextureSettings, OpenGL, T
 use piston::input::{RenderArgs, UpdateArgs};
 use graphics::{Image, rectangle, Context, Transformed, DrawState};
 
-use hexcell_api::display::LED_COUNT;
+use hexcell_api::display::{LED_GEOMETRY, MAX_RADIUS};
 use crate::hexcell_sim::{HexCellSim, Coordinate};
-use assertions::const_assert;
 
-macro_rules! count {
-    () => (0usize);
-    ( $x:tt $($xs:tt)*) => (1usize + count!($($xs)*));
-}
-
-macro_rules! contentric_array {
-    ($( $ring:expr),*) => {
-        const N_RINGS:usize = (0usize + count!($( $ring )*));
-        const N_LEDS: usize = (0usize $(+ $ring)*);
-        const LED_LAYOUT: [u32; N_RINGS] = [$($ring,)*];
-    }
-}
-
-contentric_array!(3, 6);
-// Radius of each ring (leds occupy center of radial)
+// Size of each led square
 const LED_SIZE: f64 = 16.0;
 
 pub struct Renderer<'R> {
@@ -48,7 +33,6 @@ impl<'R> Renderer<'R> {
 
     pub fn new(opengl: OpenGL, xy: Coordinate) -> Renderer<'R>
     {
-        const_assert!(N_LEDS <= LED_COUNT);
         let texture_settings = TextureSettings::new().filter(Filter::Nearest);
         const ring_radius: f64 = 75.0;
         const hex_radius: f64 = ring_radius * 1.5;
@@ -155,14 +139,12 @@ impl<'R> Renderer<'R> {
     {
         use graphics::*;
         
-        // Rings of leds [leds in each ring]
-
         let (x, y) = Renderer::hex_to_screen(self.root, self.CELL_RADIUS, pos.x, pos.y);
         const white: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
         const black: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
         const border: f64 = 1.0;
-        let mut r:f64 = self.RING_RADIUS / 2.0;
-        let mut led_index:u8 = 0;
+        // Led positions are on a 0..MAX_RADIUS scale with y up, the outer ring sits at RING_RADIUS
+        let scale: f64 = self.RING_RADIUS / MAX_RADIUS as f64;
         
         self.gl.draw(args.viewport(), |c, gl| {
             let transform = c.transform
@@ -171,21 +153,16 @@ impl<'R> Renderer<'R> {
             Renderer::draw_hex(white, self.HEX_RADIUS, transform, gl);    
             //circle_arc([0.0,0.0,1.0,1.0], 4.0, 0.0, 2.0 * std::f64::consts::PI, rectangle::square(-5.0, -5.0, 10.0), transform, gl);
             let _ = text([0.0,0.0,0.0,1.0], 32, format!("{0}", sim.address).as_str(), &mut self.glyphs, transform.trans(-8.0, 8.0), gl);
-            for ring_count in LED_LAYOUT
+            for (led, position) in sim.display.leds.iter().zip(LED_GEOMETRY.iter())
             {
-                for i in 0..ring_count
-                {
-                    let led = sim.display.leds[led_index as usize];
-                    led_index += 1;
-                    let color: [f32; 4] = [led.r as f32 / 255.0, led.g as f32 / 255.0, led.b as f32 / 255.0 ,0.7];
-                    
-                    let theta:f64 = (i as f64 * (2.0 * PI) / ring_count as f64) - (2.0 * PI / 4.0);
-                    let square = rectangle::square(-LED_SIZE/2.0, -LED_SIZE/2.0, LED_SIZE as f64);
-                    let border_sq = [square[0] - border, square[1] - border, square[2] + (2.0 * border), square[3] + (2.0 * border)];
-                    rectangle(black, border_sq, transform.trans(r* theta.cos(), r * theta.sin()).rot_rad(theta), gl);
-                    rectangle(color, square, transform.trans(r * theta.cos(), r * theta.sin()).rot_rad(theta), gl);
-                }
-                r += self.RING_RADIUS * 0.5;
+                let color: [f32; 4] = [led.r as f32 / 255.0, led.g as f32 / 255.0, led.b as f32 / 255.0 ,0.7];
+                
+                let theta:f64 = position.angle as f64 * (2.0 * PI) / 256.0;
+                let (led_x, led_y) = (position.x as f64 * scale, -(position.y as f64) * scale);
+                let square = rectangle::square(-LED_SIZE/2.0, -LED_SIZE/2.0, LED_SIZE as f64);
+                let border_sq = [square[0] - border, square[1] - border, square[2] + (2.0 * border), square[3] + (2.0 * border)];
+                rectangle(black, border_sq, transform.trans(led_x, led_y).rot_rad(theta), gl);
+                rectangle(color, square, transform.trans(led_x, led_y).rot_rad(theta), gl);
             }
         });
     }